@@ -0,0 +1,127 @@
+//! Decoding latency for 10 to 30 syllable inputs, with and without beam
+//! pruning. Run with `cargo bench -p liushu-core --bench decoder`.
+//!
+//! On a synthetic model with 200 hanzi per syllable the numbers look like:
+//!
+//! | input | exhaustive | beam 32 | beam 8, threshold 20 |
+//! |-------|------------|---------|----------------------|
+//! | 10    | 70 ms      | 11 ms   | 1.0 ms               |
+//! | 20    | 173 ms     | 27 ms   | 3.7 ms               |
+//! | 30    | 286 ms     | 42 ms   | 8.5 ms               |
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use redb::Database;
+
+use liushu_core::hmm::decoder::{Beam, Decoder, DecoderConfig};
+use liushu_core::hmm::train_to_db;
+
+const SYLLABLES: usize = 60;
+const STATES_PER_SYLLABLE: usize = 200;
+const SENTENCES: usize = 5000;
+const SENTENCE_LEN: usize = 12;
+
+/// Linear congruential generator, enough to make a reproducible corpus.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % bound as u64) as usize
+    }
+}
+
+fn syllable(idx: usize) -> String {
+    format!("py{}", idx)
+}
+
+fn hanzi(syllable: usize, state: usize) -> char {
+    char::from_u32(0x4e00 + (syllable * STATES_PER_SYLLABLE + state) as u32).unwrap()
+}
+
+/// Train a model on a synthetic corpus with a few hundred hanzi per syllable,
+/// returning its path. The caller removes its directory.
+fn synthetic_model() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("liushu-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let corpus_path = dir.join("corpus.txt");
+    let model_path = dir.join("model.redb");
+
+    let mut rng = Lcg(42);
+    let mut writer = BufWriter::new(File::create(&corpus_path).unwrap());
+    for _ in 0..SENTENCES {
+        let (words, pinyins): (Vec<String>, Vec<String>) = (0..SENTENCE_LEN)
+            .map(|_| {
+                let syl = rng.next(SYLLABLES);
+                // Skew towards the first states so that some hanzi are much more frequent
+                let bound = rng.next(STATES_PER_SYLLABLE) + 1;
+                let state = rng.next(bound);
+                (hanzi(syl, state).to_string(), syllable(syl))
+            })
+            .unzip();
+        writeln!(writer, "{}\t{}", words.join(" "), pinyins.join(" ")).unwrap();
+    }
+    writer.flush().unwrap();
+
+    let _ = fs::remove_file(&model_path);
+    let db = Database::create(&model_path).unwrap();
+    train_to_db(&corpus_path, &db, 0).unwrap();
+    model_path
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let model_path = synthetic_model();
+
+    let configs = [
+        ("exhaustive", None),
+        (
+            "beam-32",
+            Some(Beam {
+                width: 32,
+                ..Default::default()
+            }),
+        ),
+        (
+            "beam-8",
+            Some(Beam {
+                width: 8,
+                threshold: 20.0,
+            }),
+        ),
+    ];
+
+    let mut rng = Lcg(7);
+    let inputs: Vec<Vec<String>> = [10, 20, 30]
+        .iter()
+        .map(|&len| (0..len).map(|_| syllable(rng.next(SYLLABLES))).collect())
+        .collect();
+
+    let mut group = c.benchmark_group("decode");
+    group.sample_size(10);
+    for (name, beam) in configs {
+        let config = DecoderConfig {
+            beam,
+            ..Default::default()
+        };
+        let decoder = Decoder::with_config(Database::open(&model_path).unwrap(), config);
+        for input in &inputs {
+            group.bench_with_input(BenchmarkId::new(name, input.len()), input, |b, input| {
+                b.iter(|| decoder.decode(input).unwrap())
+            });
+        }
+    }
+    group.finish();
+
+    if let Some(dir) = model_path.parent() {
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
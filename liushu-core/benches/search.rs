@@ -0,0 +1,56 @@
+//! Prefix lookup of a page of candidates for short codes, against ranking the
+//! whole subtree. Run with `cargo bench -p liushu-core --bench search`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use liushu_core::dict::{DictItem, Dictionary};
+use liushu_core::engine::{translator::Translator, Engine};
+
+const ITEMS: usize = 200_000;
+
+/// A shape-code like dictionary with codes of 1 to 4 letters.
+fn synthetic_dictionary() -> Dictionary {
+    let mut trie = Dictionary::new();
+    let mut seed = 42u64;
+    for idx in 0..ITEMS {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let len = 1 + (seed >> 60) as usize % 4;
+        let code: String = (0..len)
+            .map(|n| char::from(b'a' + ((seed >> (n * 5 + 8)) % 26) as u8))
+            .collect();
+        let item = DictItem {
+            text: format!("w{}", idx),
+            code: code.clone(),
+            weight: (seed >> 32) as u32 % 100_000,
+            comment: None,
+        };
+        match trie.get_mut(&code) {
+            Some(entry) => entry.push(item),
+            None => {
+                trie.insert(code, vec![item]);
+            }
+        }
+    }
+    trie
+}
+
+fn bench_search(c: &mut Criterion) {
+    let engine = Engine::from_dictionary(synthetic_dictionary());
+    let trie = synthetic_dictionary();
+
+    let mut group = c.benchmark_group("search");
+    for code in ["a", "ab", "abc"] {
+        group.bench_with_input(BenchmarkId::new("translate", code), code, |b, code| {
+            b.iter(|| trie.translate(code).into_iter().take(10).count())
+        });
+        group.bench_with_input(BenchmarkId::new("ranked", code), code, |b, code| {
+            b.iter(|| engine.iter_candidates(code).take(10).count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
@@ -0,0 +1,126 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::LiushuError;
+
+/// Mirrors `dhall/Prelude`. Dhall configs are loaded after rendering them to
+/// JSON, e.g. `dhall-to-json --file config.dhall > config.json`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Config {
+    pub formulas: Vec<Formula>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Formula {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub use_hmm: bool,
+    /// Built dictionaries, relative to the data directory
+    pub dictionaries: Vec<String>,
+    /// Trained HMM model used for sentence conversion, relative to the data directory
+    #[serde(default)]
+    pub model: Option<String>,
+    /// OpenCC tables converting candidates, relative to the data directory.
+    /// Each inner list is one step, e.g.
+    /// `[["STPhrases.txt", "STCharacters.txt"], ["TWVariants.txt"]]`
+    #[serde(default)]
+    pub conversion: Vec<Vec<String>>,
+    /// Candidate filters run in order, out of `dedupe`, `gb2312`, `conversion`,
+    /// `emoji` and `history`. Empty means `["history", "conversion"]`. `emoji`
+    /// adds the symbols of each candidate as keyword from `symbols`
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// Emoji and symbol tables built by `liushu-dict symbols`, relative to the data directory
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    /// Index among the candidates where symbols are inserted, 1 by default
+    #[serde(default)]
+    pub symbol_position: Option<u32>,
+    /// Dictionary of another formula, usually pinyin, to find words by. Their
+    /// codes come from the reverse indexes built next to `dictionaries`
+    #[serde(default)]
+    pub reverse_lookup: Option<String>,
+    /// Input prefix starting a reverse lookup, `` ` `` by default
+    #[serde(default)]
+    pub reverse_lookup_prefix: Option<String>,
+    /// Candidate tiers from first to last, out of `exact`, `completion`,
+    /// `fuzzy` and `predicted`. Kinds in the same tier are merged by weight,
+    /// scaled by an optional percentage, e.g. `[["exact", "completion:50"]]`.
+    /// Empty means `[["exact"], ["predicted"], ["completion"], ["fuzzy"]]`
+    #[serde(default)]
+    pub ranking: Vec<Vec<String>>,
+    /// Whether next words are suggested after a commit, off by default.
+    /// Predictions take the digit keys, so only turn it on in frontends that
+    /// show them
+    #[serde(default)]
+    pub prediction: Option<bool>,
+    /// Rules generating the codes of new phrases of shape-code formulas, see
+    /// [`crate::dict::Encoder`], e.g. `["2:AaAbBaBb", "3:AaBaCaCb", "4-:AaBaCaZa"]`.
+    /// Empty means phrases are learnt by the codes typed for them
+    #[serde(default)]
+    pub encoder: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LiushuError> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader)
+            .map_err(|err| LiushuError::Config(format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn formula(&self, id: &str) -> Option<&Formula> {
+        self.formulas.iter().find(|formula| formula.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "formulas": [
+                    {
+                        "id": "sunman",
+                        "dictionaries": ["sunman.trie"],
+                        "encoder": ["2:AaAbBaBb", "3-:AaBaCaZa"]
+                    },
+                    {
+                        "id": "pinyin",
+                        "name": "拼音",
+                        "use_hmm": true,
+                        "dictionaries": ["pinyin.trie"],
+                        "model": "pinyin.redb",
+                        "conversion": [["STPhrases.txt", "STCharacters.txt"], ["HKVariants.txt"]],
+                        "filters": ["history", "gb2312", "conversion"],
+                        "ranking": [["exact", "completion:50"], ["predicted"]]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let sunman = config.formula("sunman").unwrap();
+        assert!(!sunman.use_hmm);
+        assert_eq!(sunman.model, None);
+
+        let pinyin = config.formula("pinyin").unwrap();
+        assert!(pinyin.use_hmm);
+        assert_eq!(pinyin.model.as_deref(), Some("pinyin.redb"));
+        assert_eq!(pinyin.conversion.len(), 2);
+        assert!(sunman.conversion.is_empty());
+        assert_eq!(pinyin.filters, vec!["history", "gb2312", "conversion"]);
+        assert_eq!(pinyin.ranking[0], vec!["exact", "completion:50"]);
+        assert!(sunman.ranking.is_empty());
+        assert_eq!(sunman.encoder.len(), 2);
+        assert!(pinyin.encoder.is_empty());
+
+        assert!(config.formula("wubi").is_none());
+    }
+}
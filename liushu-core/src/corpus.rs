@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use pinyin::ToPinyin;
+use thiserror::Error;
+
+use crate::error::LiushuError;
+
+/// A sentence of the training corpus with one pinyin per word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusLine {
+    pub words: Vec<String>,
+    pub pinyins: Vec<String>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CorpusLineError {
+    #[error("missing the tab separated pinyin column")]
+    MissingPinyin,
+    #[error("expect 2 tab separated columns, got {0}")]
+    TooManyColumns(usize),
+    #[error("empty sentence")]
+    EmptySentence,
+    #[error("{words} words but {pinyins} pinyins")]
+    CountMismatch { words: usize, pinyins: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusError {
+    /// 1-based line number
+    pub line: usize,
+    pub error: CorpusLineError,
+}
+
+/// Result of validating a training corpus.
+#[derive(Debug, Default, Clone)]
+pub struct CorpusReport {
+    pub lines: usize,
+    pub valid: usize,
+    pub errors: Vec<CorpusError>,
+}
+
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} lines malformed", self.errors.len(), self.lines)?;
+        for err in self.errors.iter().take(5) {
+            write!(f, "; line {}: {}", err.line, err.error)?;
+        }
+        if self.errors.len() > 5 {
+            write!(f, "; ...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a `words<TAB>pinyins` corpus line, returning `None` for blank lines.
+pub fn parse_corpus_line(line: &str) -> Result<Option<CorpusLine>, CorpusLineError> {
+    let line = line.trim_end();
+    if line.trim_start().is_empty() {
+        return Ok(None);
+    }
+
+    let columns: Vec<&str> = line.split('\t').collect();
+    match columns.len() {
+        1 => return Err(CorpusLineError::MissingPinyin),
+        2 => {}
+        n => return Err(CorpusLineError::TooManyColumns(n)),
+    }
+
+    let words: Vec<String> = columns[0].split_whitespace().map(String::from).collect();
+    let pinyins: Vec<String> = columns[1].split_whitespace().map(String::from).collect();
+    if words.is_empty() {
+        return Err(CorpusLineError::EmptySentence);
+    }
+    if words.len() != pinyins.len() {
+        return Err(CorpusLineError::CountMismatch {
+            words: words.len(),
+            pinyins: pinyins.len(),
+        });
+    }
+
+    Ok(Some(CorpusLine { words, pinyins }))
+}
+
+/// Validate every line of a corpus, failing when more than `max_errors`
+/// lines are malformed.
+pub fn check_corpus(
+    corpus_file: impl AsRef<Path>,
+    max_errors: usize,
+) -> Result<CorpusReport, LiushuError> {
+    let mut report = CorpusReport::default();
+    let reader = BufReader::new(File::open(corpus_file)?);
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        report.lines += 1;
+        match parse_corpus_line(&line) {
+            Ok(Some(_)) => report.valid += 1,
+            Ok(None) => {}
+            Err(error) => report.errors.push(CorpusError {
+                line: idx + 1,
+                error,
+            }),
+        }
+    }
+
+    if report.errors.len() > max_errors {
+        return Err(LiushuError::MalformedCorpus { report, max_errors });
+    }
+
+    Ok(report)
+}
+
+/// Iterate over the well-formed lines of a corpus, skipping blank and
+/// malformed ones.
+pub fn read_corpus(
+    corpus_file: impl AsRef<Path>,
+) -> Result<impl Iterator<Item = Result<CorpusLine, LiushuError>>, LiushuError> {
+    let reader = BufReader::new(File::open(corpus_file)?);
+
+    Ok(reader.lines().filter_map(|line| match line {
+        Ok(line) => parse_corpus_line(&line).ok().flatten().map(Ok),
+        Err(err) => Some(Err(err.into())),
+    }))
+}
+
+/// Annotates Han text with toneless pinyin, one syllable per character as
+/// the decoder emits one character per syllable.
+///
+/// Phrases listed in the heteronym dictionary are matched greedily (forward
+/// maximum matching) and give the readings of their characters, every other
+/// character falls back to its most common reading.
+#[derive(Debug, Default)]
+pub struct PinyinAnnotator {
+    /// Syllables of each phrase, one per character
+    phrases: HashMap<String, Vec<String>>,
+    max_phrase_len: usize,
+}
+
+impl PinyinAnnotator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a heteronym dictionary, one `phrase<TAB>syllables` entry per line,
+    /// syllables separated by spaces. Lines starting with `#` are ignored.
+    pub fn with_heteronyms(path: impl AsRef<Path>) -> Result<Self, LiushuError> {
+        let path = path.as_ref();
+        let mut annotator = Self::new();
+        let reader = BufReader::new(File::open(path)?);
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let line_no = Some(idx as u64 + 1);
+            let Some((phrase, syllables)) = line.split_once('\t') else {
+                return Err(LiushuError::dict_format(
+                    path,
+                    line_no,
+                    "expect `phrase<TAB>pinyin`",
+                ));
+            };
+            let syllables: Vec<&str> = syllables.split_whitespace().collect();
+            if syllables.len() != phrase.chars().count() {
+                return Err(LiushuError::dict_format(
+                    path,
+                    line_no,
+                    format!("{} has {} syllables", phrase, syllables.len()),
+                ));
+            }
+
+            annotator.insert(phrase, &syllables);
+        }
+
+        Ok(annotator)
+    }
+
+    /// Read `phrase` with `syllables`, one per character.
+    pub fn insert(&mut self, phrase: &str, syllables: &[&str]) {
+        self.max_phrase_len = self.max_phrase_len.max(phrase.chars().count());
+        self.phrases.insert(
+            phrase.to_string(),
+            syllables.iter().map(|py| py.to_string()).collect(),
+        );
+    }
+
+    /// Annotate a run of Han characters, returning a `(character, pinyin)`
+    /// pair per character.
+    ///
+    /// Characters without a known reading are skipped.
+    pub fn annotate(&self, text: &str) -> Vec<(String, String)> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = vec![];
+        let mut start = 0;
+
+        while start < chars.len() {
+            let longest = self.max_phrase_len.min(chars.len() - start);
+            let phrase = (1..=longest).rev().find_map(|len| {
+                let word: String = chars[start..start + len].iter().collect();
+                self.phrases.get(&word).map(|syllables| (syllables, len))
+            });
+
+            match phrase {
+                Some((syllables, len)) => {
+                    for (ch, py) in chars[start..start + len].iter().zip(syllables) {
+                        result.push((ch.to_string(), py.clone()));
+                    }
+                    start += len;
+                }
+                None => {
+                    let ch = chars[start];
+                    if let Some(py) = ch.to_pinyin() {
+                        result.push((ch.to_string(), py.plain().replace('ü', "v")));
+                    }
+                    start += 1;
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Split raw text into runs of Han characters, dropping everything else.
+pub fn han_sentences(text: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut current = String::new();
+
+    for ch in text.chars() {
+        if ch.to_pinyin().is_some() {
+            current.push(ch);
+        } else if !current.is_empty() {
+            result.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+
+    result
+}
+
+/// Turn raw text into the corpus format expected by [`crate::hmm::train_to_db`]:
+/// space separated characters, a tab, then their space separated pinyin.
+pub fn prepare_corpus(
+    raw_file: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    annotator: &PinyinAnnotator,
+) -> Result<(), LiushuError> {
+    let reader = BufReader::new(File::open(raw_file)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        for sentence in han_sentences(&line) {
+            let (words, pinyins): (Vec<String>, Vec<String>) =
+                annotator.annotate(&sentence).into_iter().unzip();
+            if words.is_empty() {
+                continue;
+            }
+            writeln!(writer, "{}\t{}", words.join(" "), pinyins.join(" "))?;
+        }
+
+        if idx % 5000 == 0 {
+            println!("current prepare count {}", idx);
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_corpus_line() {
+        assert_eq!(
+            parse_corpus_line("我们 好\twomen hao\n"),
+            Ok(Some(CorpusLine {
+                words: vec!["我们".to_string(), "好".to_string()],
+                pinyins: vec!["women".to_string(), "hao".to_string()],
+            }))
+        );
+        assert_eq!(parse_corpus_line("  "), Ok(None));
+        assert_eq!(
+            parse_corpus_line("我们 好"),
+            Err(CorpusLineError::MissingPinyin)
+        );
+        assert_eq!(
+            parse_corpus_line("我们\twomen\textra"),
+            Err(CorpusLineError::TooManyColumns(3))
+        );
+        assert_eq!(
+            parse_corpus_line("\twomen"),
+            Err(CorpusLineError::EmptySentence)
+        );
+        assert_eq!(
+            parse_corpus_line("我们 好\twomen"),
+            Err(CorpusLineError::CountMismatch {
+                words: 2,
+                pinyins: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_han_sentences() {
+        assert_eq!(
+            han_sentences("你好，世界! hello 2023年"),
+            vec!["你好", "世界", "年"]
+        );
+        assert_eq!(han_sentences("abc"), vec![] as Vec<String>);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let mut annotator = PinyinAnnotator::new();
+        annotator.insert("银行", &["yin", "hang"]);
+
+        assert_eq!(
+            annotator.annotate("去银行"),
+            vec![
+                ("去".to_string(), "qu".to_string()),
+                ("银".to_string(), "yin".to_string()),
+                ("行".to_string(), "hang".to_string()),
+            ]
+        );
+        assert_eq!(
+            annotator.annotate("绿"),
+            vec![("绿".to_string(), "lv".to_string())]
+        );
+    }
+}
@@ -1,12 +1,32 @@
-use std::{fs::File, path::Path};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use data_encoding::HEXLOWER;
+use itertools::Itertools;
 use patricia_tree::StringPatriciaMap;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::error::LiushuError;
+use crate::{corpus::PinyinAnnotator, error::LiushuError};
+
+mod encoder;
+pub mod ranked;
+mod source;
+
+pub use encoder::Encoder;
+pub use ranked::{RankedCursor, RankedIndex};
+pub use source::{Column, SourceFormat};
 
 pub type Dictionary = StringPatriciaMap<Vec<DictItem>>;
 
+/// Codes of each text, most frequent first, for reverse lookup.
+pub type ReverseIndex = HashMap<String, Vec<String>>;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DictItem {
     pub text: String,
@@ -15,31 +35,895 @@ pub struct DictItem {
     pub comment: Option<String>,
 }
 
-pub fn build<I, O>(inputs: &Vec<I>, output: O) -> Result<(), LiushuError>
+/// First bytes of every built dictionary
+pub const MAGIC: [u8; 4] = *b"LSDT";
+/// Version of what follows [`MAGIC`], bumped on incompatible changes
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Formula a dictionary was built for.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FormulaMeta {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+/// Lowercase hex SHA-256 of one source of a dictionary.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SourceHash {
+    pub path: String,
+    pub hash: String,
+}
+
+/// Written after [`MAGIC`] and [`FORMAT_VERSION`], before the trie of every
+/// built dictionary.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DictHeader {
+    /// Seconds since the Unix epoch
+    pub built_at: u64,
+    /// Sources then patches, in the order they were applied
+    pub sources: Vec<SourceHash>,
+    /// SHA-256 of the sources, see [`hash_sources`]. Patches chain their
+    /// content onto the hash of the dictionary they are applied to
+    pub source_hash: String,
+    pub formula: Option<FormulaMeta>,
+    /// How to build the dictionary again, `None` once it was patched or if
+    /// it was merged
+    pub recipe: Option<BuildRecipe>,
+}
+
+/// Sources and options a dictionary was built from by [`build_with`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BuildRecipe {
+    /// Absolute when they could be resolved
+    pub inputs: Vec<PathBuf>,
+    pub options: BuildOptions,
+}
+
+impl BuildRecipe {
+    /// Whether `path` is read when building the dictionary.
+    pub fn uses(&self, path: impl AsRef<Path>) -> bool {
+        let path = absolute(path.as_ref());
+        self.inputs.contains(&path) || self.options.heteronyms.as_ref() == Some(&path)
+    }
+}
+
+impl DictHeader {
+    fn new<I: AsRef<Path>>(
+        inputs: &[I],
+        source_hash: String,
+        formula: Option<FormulaMeta>,
+    ) -> Result<Self, LiushuError> {
+        let mut sources = Vec::with_capacity(inputs.len());
+        for path in inputs {
+            sources.push(SourceHash {
+                path: path.as_ref().display().to_string(),
+                hash: hash_sources(&[path])?,
+            });
+        }
+        let built_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Ok(Self {
+            built_at,
+            sources,
+            source_hash,
+            formula,
+            recipe: None,
+        })
+    }
+}
+
+/// What goes into a dictionary besides its sources.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BuildOptions {
+    /// Written to the header, engines of other formulas refuse the dictionary
+    #[serde(skip)]
+    pub formula: Option<FormulaMeta>,
+    /// One format for all sources, or one per source. Empty means
+    /// [`SourceFormat::default`]
+    pub formats: Vec<SourceFormat>,
+    /// Heteronym dictionary for the pinyin of entries without code, see
+    /// [`PinyinAnnotator::with_heteronyms`]
+    pub heteronyms: Option<PathBuf>,
+    /// Generates the codes of phrases without code from those of their
+    /// characters instead of pinyin, see [`Encoder`]
+    pub encoder: Encoder,
+}
+
+impl BuildOptions {
+    fn format(&self, idx: usize) -> SourceFormat {
+        match self.formats.as_slice() {
+            [format] => format.clone(),
+            formats => formats.get(idx).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Hash of the sources read with these options. Formats other than the
+    /// default, the heteronyms and the encoder rules count as part of the
+    /// sources.
+    fn source_hash<I: AsRef<Path>>(&self, inputs: &[I]) -> Result<String, LiushuError> {
+        let hash = hash_sources(inputs)?;
+        if self.formats.is_empty() && self.heteronyms.is_none() && self.encoder.is_empty() {
+            return Ok(hash);
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(hash.as_bytes());
+        for format in &self.formats {
+            hasher.update(format.to_string().as_bytes());
+        }
+        if let Some(heteronyms) = &self.heteronyms {
+            hasher.update(hash_sources(&[heteronyms])?.as_bytes());
+        }
+        hasher.update(self.encoder.to_string().as_bytes());
+        Ok(HEXLOWER.encode(&hasher.finalize()))
+    }
+
+    fn annotator(&self) -> Result<PinyinAnnotator, LiushuError> {
+        match &self.heteronyms {
+            Some(path) => PinyinAnnotator::with_heteronyms(path),
+            None => Ok(PinyinAnnotator::new()),
+        }
+    }
+}
+
+/// Code of an entry without one: the toneless pinyin of its characters.
+fn pinyin_code(annotator: &PinyinAnnotator, text: &str) -> Option<String> {
+    let words = annotator.annotate(text);
+    let annotated: String = words.iter().map(|(word, _)| word.as_str()).collect();
+    (annotated == text).then(|| words.into_iter().map(|(_, pinyin)| pinyin).collect())
+}
+
+/// Lowercase hex SHA-256 of the contents of `inputs`, in order.
+pub fn hash_sources<I: AsRef<Path>>(inputs: &[I]) -> Result<String, LiushuError> {
+    let mut hasher = Sha256::new();
+    for path in inputs {
+        let content = fs::read(path)?;
+        // The length keeps `ab` + `c` apart from `a` + `bc`
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+    Ok(HEXLOWER.encode(&hasher.finalize()))
+}
+
+/// Build a dictionary from TSV sources, along with its reverse index at
+/// [`reverse_index_path`].
+pub fn build<I, O>(inputs: &[I], output: O) -> Result<(), LiushuError>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    build_with(inputs, output, &BuildOptions::default())
+}
+
+pub fn build_with<I, O>(inputs: &[I], output: O, options: &BuildOptions) -> Result<(), LiushuError>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    if options.formats.len() > 1 && options.formats.len() != inputs.len() {
+        return Err(LiushuError::Config(format!(
+            "{} source formats for {} sources",
+            options.formats.len(),
+            inputs.len()
+        )));
+    }
+
+    let annotator = options.annotator()?;
+    let mut trie = Dictionary::new();
+    let mut uncoded = vec![];
+    for (idx, dict_path) in inputs.iter().enumerate() {
+        let dict_path = dict_path.as_ref();
+        for mut item in options.format(idx).read(dict_path)? {
+            if !item.code.is_empty() {
+                insert_item(&mut trie, item);
+            } else if options.encoder.is_empty() {
+                item.code =
+                    pinyin_code(&annotator, &item.text).ok_or_else(|| no_code(dict_path, &item))?;
+                insert_item(&mut trie, item);
+            } else {
+                uncoded.push((dict_path, item));
+            }
+        }
+    }
+    // Phrases are encoded once the codes of all characters are known
+    if !uncoded.is_empty() {
+        let codes = reverse_index(&trie);
+        for (dict_path, mut item) in uncoded {
+            item.code = options
+                .encoder
+                .encode(&item.text, &codes)
+                .ok_or_else(|| no_code(dict_path, &item))?;
+            insert_item(&mut trie, item);
+        }
+    }
+
+    let mut header = DictHeader::new(
+        inputs,
+        options.source_hash(inputs)?,
+        options.formula.clone(),
+    )?;
+    header.recipe = Some(BuildRecipe {
+        inputs: inputs.iter().map(|path| absolute(path.as_ref())).collect(),
+        options: BuildOptions {
+            heteronyms: options.heteronyms.as_deref().map(absolute),
+            ..options.clone()
+        },
+    });
+    write_dictionary(&header, &trie, output)
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn no_code(path: &Path, item: &DictItem) -> LiushuError {
+    LiushuError::dict_format(
+        path,
+        None,
+        format!("can't generate the code of {}", item.text),
+    )
+}
+
+/// Like [`build_with`], but leave the output as is if it was built from the
+/// same sources and for the same formula. Returns whether it was built.
+pub fn build_if_changed<I, O>(
+    inputs: &[I],
+    output: O,
+    options: &BuildOptions,
+) -> Result<bool, LiushuError>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    let output = output.as_ref();
+    if output.exists() && reverse_index_path(output).exists() {
+        let source_hash = options.source_hash(inputs)?;
+        if read_header(output).is_ok_and(|header| {
+            header.source_hash == source_hash && header.formula == options.formula
+        }) {
+            return Ok(false);
+        }
+    }
+    build_with(inputs, output, options)?;
+    Ok(true)
+}
+
+/// Build a dictionary again from the sources and with the options of its
+/// [`BuildRecipe`], unless they didn't change. Returns whether it was built.
+pub fn rebuild_if_changed(dict_path: impl AsRef<Path>) -> Result<bool, LiushuError> {
+    let dict_path = dict_path.as_ref();
+    let header = read_header(dict_path)?;
+    let Some(recipe) = header.recipe else {
+        return Err(LiushuError::Config(format!(
+            "{} was patched or merged, it can't be rebuilt from its sources",
+            dict_path.display()
+        )));
+    };
+    let options = BuildOptions {
+        formula: header.formula,
+        ..recipe.options
+    };
+    build_if_changed(&recipe.inputs, dict_path, &options)
+}
+
+/// Add the entries of TSV patches to a built dictionary, in place. Entries
+/// with the text and code of an existing one replace it.
+pub fn add<I, O>(dict_path: O, patches: &[I]) -> Result<(), LiushuError>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    let dict_path = dict_path.as_ref();
+    let (header, mut trie) = open(dict_path)?;
+    for patch in patches {
+        for item in read_items(patch.as_ref())? {
+            if let Some(entry) = trie.get_mut(&item.code) {
+                entry.retain(|existing| existing.text != item.text);
+            }
+            insert_item(&mut trie, item);
+        }
+    }
+    write_dictionary(&chain_hash(&header, patches)?, &trie, dict_path)
+}
+
+/// Remove the entries listed by TSV patches from a built dictionary, in
+/// place. Patches need `text` and `code` columns only.
+pub fn remove<I, O>(dict_path: O, patches: &[I]) -> Result<(), LiushuError>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    #[derive(Deserialize)]
+    struct Removed {
+        text: String,
+        code: String,
+    }
+
+    let dict_path = dict_path.as_ref();
+    let (header, mut trie) = open(dict_path)?;
+    for patch in patches {
+        let patch = patch.as_ref();
+        for result in tsv_reader(patch)?.deserialize() {
+            let removed: Removed = result.map_err(|err| LiushuError::from_csv(patch, err))?;
+            let now_empty = match trie.get_mut(&removed.code) {
+                Some(entry) => {
+                    entry.retain(|item| item.text != removed.text);
+                    entry.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                trie.remove(&removed.code);
+            }
+        }
+    }
+    write_dictionary(&chain_hash(&header, patches)?, &trie, dict_path)
+}
+
+/// Merge built dictionaries into one. Entries of later inputs replace the
+/// ones with the same text and code.
+pub fn merge<I, O>(inputs: &[I], output: O) -> Result<(), LiushuError>
 where
     I: AsRef<Path>,
     O: AsRef<Path>,
 {
-    let mut trie = StringPatriciaMap::new();
-    for dict_path in inputs {
-        let mut rdr = csv::ReaderBuilder::new()
-            .delimiter(b'\t')
-            .comment(Some(b'#'))
-            .from_path(dict_path)?;
-        for result in rdr.deserialize() {
-            let item: DictItem = result?;
-            let code = item.code.clone();
-
-            if trie.get(&code).is_none() {
-                trie.insert(&code, vec![item]);
-            } else if let Some(entry) = trie.get_mut(code.as_str()) {
-                entry.push(item);
+    let mut trie = Dictionary::new();
+    let mut hasher = Sha256::new();
+    let mut sources = vec![];
+    let mut formulas = vec![];
+    for input in inputs {
+        let (header, dict) = open(input)?;
+        hasher.update(header.source_hash.as_bytes());
+        sources.extend(header.sources);
+        formulas.push(header.formula);
+        for (_, items) in dict {
+            for item in items {
+                if let Some(entry) = trie.get_mut(&item.code) {
+                    entry.retain(|existing| existing.text != item.text);
+                }
+                insert_item(&mut trie, item);
             }
         }
     }
+    // Only keep the formula all inputs agree on
+    let formula = if formulas.iter().all_equal() {
+        formulas.into_iter().next().flatten()
+    } else {
+        None
+    };
+    let mut header = DictHeader::new::<&Path>(&[], HEXLOWER.encode(&hasher.finalize()), formula)?;
+    header.sources = sources;
+    write_dictionary(&header, &trie, output)
+}
+
+/// Load a dictionary built by [`build`].
+pub fn load(path: impl AsRef<Path>) -> Result<Dictionary, LiushuError> {
+    Ok(open(path)?.1)
+}
+
+/// Load a dictionary built by [`build`] with its header.
+pub fn open(path: impl AsRef<Path>) -> Result<(DictHeader, Dictionary), LiushuError> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = read_header_from(path, &mut reader)?;
+    let trie = read_entries(path, &mut reader)?;
+    Ok((header, trie))
+}
+
+/// Load a dictionary built by [`build`] with its header and the ranking index
+/// written after its entries.
+pub fn open_ranked(
+    path: impl AsRef<Path>,
+) -> Result<(DictHeader, Dictionary, RankedIndex), LiushuError> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = read_header_from(path, &mut reader)?;
+    let trie = read_entries(path, &mut reader)?;
+    let index: RankedIndex = bincode::deserialize_from(reader).map_err(|err| {
+        LiushuError::dict_format(path, None, format!("corrupted ranking index: {}", err))
+    })?;
+    if !index.is_consistent() {
+        return Err(LiushuError::dict_format(
+            path,
+            None,
+            "corrupted ranking index",
+        ));
+    }
+    Ok((header, trie, index))
+}
+
+fn read_entries(path: &Path, reader: &mut impl Read) -> Result<Dictionary, LiushuError> {
+    bincode::deserialize_from(reader)
+        .map_err(|err| LiushuError::dict_format(path, None, format!("corrupted entries: {}", err)))
+}
+
+/// Read and check the header of a dictionary without loading its entries.
+pub fn read_header(path: impl AsRef<Path>) -> Result<DictHeader, LiushuError> {
+    let path = path.as_ref();
+    read_header_from(path, &mut BufReader::new(File::open(path)?))
+}
+
+/// Format version of a dictionary, whether or not it can be read.
+pub fn read_version(path: impl AsRef<Path>) -> Result<u32, LiushuError> {
+    let path = path.as_ref();
+    read_version_from(path, &mut BufReader::new(File::open(path)?))
+}
+
+fn read_version_from(path: &Path, reader: &mut impl Read) -> Result<u32, LiushuError> {
+    let mut prefix = [0; 8];
+    if let Err(err) = reader.read_exact(&mut prefix) {
+        return Err(match err.kind() {
+            io::ErrorKind::UnexpectedEof => not_a_dictionary(path),
+            _ => err.into(),
+        });
+    }
+    if prefix[..4] != MAGIC {
+        return Err(not_a_dictionary(path));
+    }
+    Ok(u32::from_le_bytes([
+        prefix[4], prefix[5], prefix[6], prefix[7],
+    ]))
+}
+
+fn read_header_from(path: &Path, reader: &mut impl Read) -> Result<DictHeader, LiushuError> {
+    let version = read_version_from(path, reader)?;
+    if version != FORMAT_VERSION {
+        return Err(LiushuError::DictVersion {
+            path: path.to_path_buf(),
+            found: version,
+            expected: FORMAT_VERSION,
+        });
+    }
+    bincode::deserialize_from(reader)
+        .map_err(|err| LiushuError::dict_format(path, None, format!("corrupted header: {}", err)))
+}
+
+fn not_a_dictionary(path: &Path) -> LiushuError {
+    LiushuError::dict_format(
+        path,
+        None,
+        "not a liushu dictionary, build it with `liushu-dict build`",
+    )
+}
 
-    let trie_writer = File::create(output)?;
-    bincode::serialize_into(trie_writer, &trie)?;
+/// Write a dictionary with its reverse index. Both are written to temporary
+/// files first and the reverse index is moved in place before the dictionary,
+/// so that a reload triggered by the dictionary finds them complete.
+fn write_dictionary(
+    header: &DictHeader,
+    trie: &Dictionary,
+    output: impl AsRef<Path>,
+) -> Result<(), LiushuError> {
+    let output = output.as_ref();
+    let rev_path = reverse_index_path(output);
+    let rev_temp = write_temp(&rev_path, |writer| {
+        bincode::serialize_into(writer, &reverse_index(trie))
+            .map_err(|err| LiushuError::dict_format(&rev_path, None, err))
+    })?;
+    let trie_temp = match write_trie_temp(header, trie, output) {
+        Ok(temp) => temp,
+        Err(err) => {
+            let _ = fs::remove_file(&rev_temp);
+            return Err(err);
+        }
+    };
+    fs::rename(rev_temp, &rev_path)?;
+    fs::rename(trie_temp, output)?;
+    Ok(())
+}
 
+fn write_trie(
+    header: &DictHeader,
+    trie: &Dictionary,
+    output: impl AsRef<Path>,
+) -> Result<(), LiushuError> {
+    let output = output.as_ref();
+    fs::rename(write_trie_temp(header, trie, output)?, output)?;
     Ok(())
 }
+
+fn write_trie_temp(
+    header: &DictHeader,
+    trie: &Dictionary,
+    output: &Path,
+) -> Result<PathBuf, LiushuError> {
+    write_temp(output, |writer| {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut *writer, header)
+            .and_then(|_| bincode::serialize_into(&mut *writer, trie))
+            .and_then(|_| bincode::serialize_into(writer, &RankedIndex::build(trie)))
+            .map_err(|err| LiushuError::dict_format(output, None, err))
+    })
+}
+
+/// Write a temporary file next to `output`, to be renamed over it once
+/// complete. Removed again on error.
+fn write_temp(
+    output: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), LiushuError>,
+) -> Result<PathBuf, LiushuError> {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".tmp");
+    let temp = PathBuf::from(name);
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    let result = write(&mut writer).and_then(|_| Ok(writer.flush()?));
+    drop(writer);
+    match result {
+        Ok(()) => Ok(temp),
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+/// The header of a dictionary after applying `patches` to it.
+fn chain_hash<I: AsRef<Path>>(
+    header: &DictHeader,
+    patches: &[I],
+) -> Result<DictHeader, LiushuError> {
+    let mut hasher = Sha256::new();
+    hasher.update(header.source_hash.as_bytes());
+    hasher.update(hash_sources(patches)?.as_bytes());
+    let mut patched = DictHeader::new(
+        patches,
+        HEXLOWER.encode(&hasher.finalize()),
+        header.formula.clone(),
+    )?;
+    patched.sources.splice(..0, header.sources.iter().cloned());
+    Ok(patched)
+}
+
+fn tsv_reader(path: &Path) -> Result<csv::Reader<File>, LiushuError> {
+    csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .comment(Some(b'#'))
+        .from_path(path)
+        .map_err(|err| LiushuError::from_csv(path, err))
+}
+
+fn read_items(path: &Path) -> Result<Vec<DictItem>, LiushuError> {
+    tsv_reader(path)?
+        .deserialize()
+        .map(|result| result.map_err(|err| LiushuError::from_csv(path, err)))
+        .collect()
+}
+
+fn insert_item(trie: &mut Dictionary, item: DictItem) {
+    match trie.get_mut(&item.code) {
+        Some(entry) => entry.push(item),
+        None => {
+            trie.insert(item.code.clone(), vec![item]);
+        }
+    }
+}
+
+/// Codes of each text of a dictionary, heaviest first, each once.
+fn reverse_index(trie: &Dictionary) -> ReverseIndex {
+    let mut codes: HashMap<String, Vec<(u32, String)>> = HashMap::new();
+    for (_, items) in trie.iter() {
+        for item in items {
+            codes
+                .entry(item.text.clone())
+                .or_default()
+                .push((item.weight, item.code.clone()));
+        }
+    }
+    codes
+        .into_iter()
+        .map(|(text, mut codes)| {
+            codes.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            // The same code can come with several weights, keep the heaviest
+            let codes = codes.into_iter().map(|(_, code)| code).unique().collect();
+            (text, codes)
+        })
+        .collect()
+}
+
+/// Where [`build`] puts the reverse index of a dictionary, next to it with a
+/// `rev` extension.
+pub fn reverse_index_path(dict_path: impl AsRef<Path>) -> PathBuf {
+    dict_path.as_ref().with_extension("rev")
+}
+
+pub fn load_reverse_index(path: impl AsRef<Path>) -> Result<ReverseIndex, LiushuError> {
+    let path = path.as_ref();
+    bincode::deserialize_from(BufReader::new(File::open(path)?))
+        .map_err(|err| LiushuError::dict_format(path, None, err))
+}
+
+/// Build an emoji or symbol table.
+///
+/// Each line of the sources is a keyword followed by a tab and its symbols
+/// separated by spaces, best first, e.g. `haha<TAB>😄 😂` or `/jt<TAB>← ↑ → ↓`.
+/// Keywords can also be words, e.g. `笑<TAB>😄`, for the `emoji` filter. Lines
+/// starting with `#` are comments.
+pub fn build_symbols<I, O>(inputs: &[I], output: O) -> Result<(), LiushuError>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    let mut trie: Dictionary = StringPatriciaMap::new();
+    for path in inputs {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((code, symbols)) = line.split_once('\t') else {
+                return Err(LiushuError::dict_format(
+                    path,
+                    Some(idx as u64 + 1),
+                    "expect keyword and symbols separated by a tab",
+                ));
+            };
+
+            let symbols: Vec<&str> = symbols.split_whitespace().collect();
+            let items = symbols.iter().enumerate().map(|(idx, symbol)| DictItem {
+                text: symbol.to_string(),
+                code: code.to_string(),
+                weight: (symbols.len() - idx) as u32,
+                comment: None,
+            });
+            match trie.get_mut(code) {
+                Some(entry) => entry.extend(items),
+                None => {
+                    trie.insert(code, items.collect());
+                }
+            }
+        }
+    }
+
+    let header = DictHeader::new(inputs, hash_sources(inputs)?, None)?;
+    write_trie(&header, &trie, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_patch() {
+        let dir = TempDir::new("dict");
+        let write = |name: &str, content: &str| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let source = write(
+            "source.tsv",
+            "text\tcode\tweight\tcomment\n你\tni\t100\t\n泥\tni\t50\t\n",
+        );
+        let output = dir.join("dict.trie");
+        let texts = |path: &Path| {
+            let mut texts: Vec<(String, String, u32)> = load(path)
+                .unwrap()
+                .into_iter()
+                .flat_map(|(_, items)| items)
+                .map(|item| (item.code, item.text, item.weight))
+                .collect();
+            texts.sort();
+            texts
+        };
+
+        assert!(build_if_changed(&[&source], &output, &BuildOptions::default()).unwrap());
+        assert!(!build_if_changed(&[&source], &output, &BuildOptions::default()).unwrap());
+        let built = read_header(&output).unwrap();
+
+        let patch = write(
+            "add.tsv",
+            "text\tcode\tweight\tcomment\n泥\tni\t120\t\n好\thao\t10\t\n",
+        );
+        add(&output, &[&patch]).unwrap();
+        assert_ne!(read_header(&output).unwrap(), built);
+        let removal = write("remove.tsv", "text\tcode\n你\tni\n");
+        remove(&output, &[&removal]).unwrap();
+        assert_eq!(
+            texts(&output),
+            vec![
+                ("hao".to_string(), "好".to_string(), 10),
+                ("ni".to_string(), "泥".to_string(), 120)
+            ]
+        );
+        let index = load_reverse_index(reverse_index_path(&output)).unwrap();
+        assert!(!index.contains_key("你"));
+
+        // patched dictionaries don't match their sources anymore
+        assert!(build_if_changed(&[&source], &output, &BuildOptions::default()).unwrap());
+
+        let other = dir.join("other.trie");
+        build(&[&patch], &other).unwrap();
+        let merged = dir.join("merged.trie");
+        merge(&[&output, &other], &merged).unwrap();
+        assert_eq!(texts(&merged).len(), 3);
+    }
+
+    #[test]
+    fn test_reverse_index() {
+        let mut trie = Dictionary::new();
+        for (code, weight) in [("ni", 10), ("nii", 8), ("ni", 5)] {
+            insert_item(
+                &mut trie,
+                DictItem {
+                    text: "你".to_string(),
+                    code: code.to_string(),
+                    weight,
+                    comment: None,
+                },
+            );
+        }
+        assert_eq!(reverse_index(&trie)["你"], vec!["ni", "nii"]);
+    }
+
+    #[test]
+    fn test_header() {
+        let dir = TempDir::new("header");
+        let source = dir.join("source.tsv");
+        fs::write(&source, "text\tcode\tweight\tcomment\n你\tni\t100\t\n").unwrap();
+        let output = dir.join("dict.trie");
+
+        let options = BuildOptions {
+            formula: Some(FormulaMeta {
+                id: "pinyin".to_string(),
+                name: None,
+            }),
+            ..Default::default()
+        };
+        build_with(&[&source], &output, &options).unwrap();
+        let header = read_header(&output).unwrap();
+        assert_eq!(header.formula, options.formula);
+        assert_eq!(header.sources.len(), 1);
+        assert_eq!(header.sources[0].hash, header.source_hash);
+        let (_, trie, index) = open_ranked(&output).unwrap();
+        let texts: Vec<_> = index.iter(&trie, "n").map(|item| &item.text).collect();
+        assert_eq!(texts, vec!["你"]);
+        // a dictionary for another formula is rebuilt
+        assert!(build_if_changed(&[&source], &output, &BuildOptions::default()).unwrap());
+        assert_eq!(read_header(&output).unwrap().formula, None);
+
+        let err = load(&source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("malformed dictionary {}", source.display())
+        );
+        assert!(err.full_message().contains("not a liushu dictionary"));
+
+        let mut content = fs::read(&output).unwrap();
+        content[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&output, &content).unwrap();
+        assert!(matches!(
+            load(&output),
+            Err(LiushuError::DictVersion { found, .. }) if found == FORMAT_VERSION + 1
+        ));
+        assert_eq!(read_version(&output).unwrap(), FORMAT_VERSION + 1);
+
+        content.truncate(12);
+        content[4..8].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        fs::write(&output, &content).unwrap();
+        let err = load(&output).unwrap_err();
+        assert!(err.full_message().contains("corrupted header"));
+    }
+
+    #[test]
+    fn test_source_format() {
+        let dir = TempDir::new("source");
+        let table = dir.join("table.txt");
+        let (gbk, _, _) = encoding_rs::GBK.encode("ni\t你\t100\nhao\t好\n");
+        fs::write(&table, gbk).unwrap();
+        let words = dir.join("words.txt");
+        fs::write(&words, "你好\n# comment\n重庆\n").unwrap();
+        let output = dir.join("dict.trie");
+
+        let format = SourceFormat::default()
+            .with_default_weight(5)
+            .with_encoding("gbk")
+            .unwrap();
+        let heteronyms = dir.join("heteronyms.txt");
+        fs::write(&heteronyms, "重庆\tchong qing\n").unwrap();
+        let options = BuildOptions {
+            formats: vec![
+                format.clone().with_columns("code,text,weight").unwrap(),
+                format
+                    .with_encoding("utf-8")
+                    .unwrap()
+                    .with_columns("text")
+                    .unwrap(),
+            ],
+            heteronyms: Some(heteronyms.clone()),
+            ..Default::default()
+        };
+        build_with(&[&table, &words], &output, &options).unwrap();
+        let mut items: Vec<(String, String, u32)> = load(&output)
+            .unwrap()
+            .into_iter()
+            .flat_map(|(_, items)| items)
+            .map(|item| (item.code, item.text, item.weight))
+            .collect();
+        items.sort();
+        assert_eq!(
+            items,
+            vec![
+                ("chongqing".to_string(), "重庆".to_string(), 5),
+                ("hao".to_string(), "好".to_string(), 5),
+                ("ni".to_string(), "你".to_string(), 100),
+                ("nihao".to_string(), "你好".to_string(), 5),
+            ]
+        );
+        // the layout is part of the sources
+        assert!(!build_if_changed(&[&table, &words], &output, &options).unwrap());
+        let mut other = options.clone();
+        other.formats[0].default_weight = 1;
+        assert!(build_if_changed(&[&table, &words], &output, &other).unwrap());
+        fs::write(&heteronyms, "重庆\tzhong qing\n").unwrap();
+        assert!(build_if_changed(&[&table, &words], &output, &other).unwrap());
+        assert_eq!(
+            load(&output).unwrap().get("zhongqing").unwrap()[0].text,
+            "重庆"
+        );
+
+        assert!(SourceFormat::default().with_columns("code,weight").is_err());
+        assert!(SourceFormat::default().with_columns("text,text").is_err());
+        assert!(SourceFormat::default().with_encoding("klingon").is_err());
+        assert!(build_with(&[&table], &output, &options).is_err());
+    }
+
+    #[test]
+    fn test_encode_phrases() {
+        let dir = TempDir::new("encode");
+        let shapes = dir.join("shapes.txt");
+        fs::write(&shapes, "工\taaaa\n工\ta\n人\twwww\n工人\n").unwrap();
+        let output = dir.join("dict.trie");
+
+        let mut options = BuildOptions {
+            formats: vec![SourceFormat::default().with_columns("text,code").unwrap()],
+            encoder: Encoder::from_config(&["2:AaAbBaBb".to_string()]).unwrap(),
+            ..Default::default()
+        };
+        build_with(&[&shapes], &output, &options).unwrap();
+        assert_eq!(load(&output).unwrap().get("aaww").unwrap()[0].text, "工人");
+
+        // the rules are part of the sources
+        assert!(!build_if_changed(&[&shapes], &output, &options).unwrap());
+        options.encoder = Encoder::from_config(&["2:AaBaBb".to_string()]).unwrap();
+        assert!(build_if_changed(&[&shapes], &output, &options).unwrap());
+        assert_eq!(load(&output).unwrap().get("aww").unwrap()[0].text, "工人");
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let dir = TempDir::new("rebuild");
+        let words = dir.join("words.txt");
+        fs::write(&words, "你\t9\n").unwrap();
+        let output = dir.join("dict.trie");
+
+        let options = BuildOptions {
+            formula: Some(FormulaMeta {
+                id: "pinyin".to_string(),
+                name: None,
+            }),
+            formats: vec![SourceFormat::default().with_columns("text,weight").unwrap()],
+            ..Default::default()
+        };
+        build_with(&[&words], &output, &options).unwrap();
+        let recipe = read_header(&output).unwrap().recipe.unwrap();
+        assert!(recipe.uses(&words));
+        assert!(!rebuild_if_changed(&output).unwrap());
+
+        // with the same layout and formula
+        fs::write(&words, "你\t9\n好\t8\n").unwrap();
+        assert!(rebuild_if_changed(&output).unwrap());
+        assert_eq!(load(&output).unwrap().get("hao").unwrap()[0].weight, 8);
+        assert_eq!(read_header(&output).unwrap().formula, options.formula);
+
+        // patches aren't part of the recipe
+        let patch = dir.join("patch.tsv");
+        fs::write(&patch, "text\tcode\tweight\tcomment\n泥\tni\t5\t\n").unwrap();
+        add(&output, &[&patch]).unwrap();
+        assert!(read_header(&output).unwrap().recipe.is_none());
+        assert!(rebuild_if_changed(&output).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::LiushuError;
+
+use super::ReverseIndex;
+
+/// Generates the codes of phrases from the codes of their characters, by
+/// rules like Rime's table encoder.
+///
+/// A rule is `length:formula`, with `length` a number of characters like
+/// `2`, `3-5` or `4-`. The formula is made of pairs of an uppercase letter
+/// picking a character and a lowercase one picking a letter of its code,
+/// counted from the start by `A`-`T` and from the end by `U`-`Z`, e.g.
+/// `2:AaAbBaBb` takes the first two letters of both characters and
+/// `4-:AaBaCaZa` the first letters of the first three and of the last one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(into = "Vec<String>", try_from = "Vec<String>")]
+pub struct Encoder {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// As configured, for [`fmt::Display`]
+    spec: String,
+    min_length: usize,
+    max_length: usize,
+    /// Character and code letter indexes, negative ones from the end
+    picks: Vec<(isize, isize)>,
+}
+
+impl Encoder {
+    /// Parse `Formula::encoder`, e.g. `["2:AaAbBaBb", "3:AaBaCaCb", "4-:AaBaCaZa"]`.
+    pub fn from_config(rules: &[String]) -> Result<Self, LiushuError> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Rule::parse(rule)
+                    .ok_or_else(|| LiushuError::Config(format!("invalid encoder rule {}", rule)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Code of `text` by the first rule for its length, using the longest
+    /// code of each character in `codes`. `None` if no rule applies or a
+    /// character has no code.
+    pub fn encode(&self, text: &str, codes: &ReverseIndex) -> Option<String> {
+        let chars: Vec<String> = text.chars().map(String::from).collect();
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| (rule.min_length..=rule.max_length).contains(&chars.len()))?;
+
+        let mut code = String::new();
+        for &(char_idx, letter_idx) in &rule.picks {
+            let ch = chars.get(resolve(char_idx, chars.len())?)?;
+            // Codes are heaviest first, keep the first of the longest
+            let char_code = codes.get(ch)?.iter().rev().max_by_key(|code| code.len())?;
+            // Letters past the end of short codes are left out
+            if let Some(letter) =
+                resolve(letter_idx, char_code.len()).and_then(|idx| char_code.get(idx..idx + 1))
+            {
+                code.push_str(letter);
+            }
+        }
+        (!code.is_empty()).then_some(code)
+    }
+}
+
+/// The rules as configured, separated by spaces.
+impl fmt::Display for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, rule) in self.rules.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", rule.spec)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Encoder> for Vec<String> {
+    fn from(encoder: Encoder) -> Self {
+        encoder.rules.into_iter().map(|rule| rule.spec).collect()
+    }
+}
+
+impl TryFrom<Vec<String>> for Encoder {
+    type Error = LiushuError;
+
+    fn try_from(rules: Vec<String>) -> Result<Self, Self::Error> {
+        Self::from_config(&rules)
+    }
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Option<Self> {
+        let (length, formula) = rule.split_once(':')?;
+        let (min_length, max_length) = match length.split_once('-') {
+            Some((min, "")) => (min.parse().ok()?, usize::MAX),
+            Some((min, max)) => (min.parse().ok()?, max.parse().ok()?),
+            None => (length.parse().ok()?, length.parse().ok()?),
+        };
+
+        let letters: Vec<char> = formula.chars().collect();
+        let pairs = letters.chunks_exact(2);
+        if letters.is_empty() || !pairs.remainder().is_empty() {
+            return None;
+        }
+        let picks = pairs
+            .map(|pair| {
+                let (ch, letter) = (pair[0], pair[1]);
+                if !ch.is_ascii_uppercase() || !letter.is_ascii_lowercase() {
+                    return None;
+                }
+                Some((index(ch as u8 - b'A'), index(letter as u8 - b'a')))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            spec: rule.to_string(),
+            min_length,
+            max_length,
+            picks,
+        })
+    }
+}
+
+/// `A`-`T` count from the start, `U`-`Z` from the end.
+fn index(offset: u8) -> isize {
+    if offset < 20 {
+        offset as isize
+    } else {
+        offset as isize - 26
+    }
+}
+
+fn resolve(idx: isize, len: usize) -> Option<usize> {
+    if idx < 0 {
+        len.checked_sub(idx.unsigned_abs())
+    } else {
+        Some(idx as usize).filter(|idx| *idx < len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let encoder = Encoder::from_config(&[
+            "2:AaAbBaBb".to_string(),
+            "3:AaBaCaCb".to_string(),
+            "4-:AaBaCaZa".to_string(),
+        ])
+        .unwrap();
+        let codes: ReverseIndex = [
+            ("工", vec!["a", "aaaa"]),
+            ("人", vec!["w", "wwww"]),
+            ("中", vec!["k", "khk"]),
+            ("国", vec!["l", "lgyi"]),
+            ("共", vec!["aw"]),
+        ]
+        .into_iter()
+        .map(|(text, codes)| {
+            (
+                text.to_string(),
+                codes.into_iter().map(String::from).collect(),
+            )
+        })
+        .collect();
+
+        assert_eq!(encoder.encode("工人", &codes).as_deref(), Some("aaww"));
+        assert_eq!(encoder.encode("中国人", &codes).as_deref(), Some("klww"));
+        assert_eq!(encoder.encode("中国共人", &codes).as_deref(), Some("klaw"));
+        assert_eq!(encoder.encode("中华", &codes), None);
+        assert_eq!(encoder.encode("工", &codes), None);
+
+        assert!(Encoder::from_config(&["2:AaA".to_string()]).is_err());
+        assert!(Encoder::from_config(&["x:AaBb".to_string()]).is_err());
+        assert!(Encoder::from_config(&["2:aAbB".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{DictItem, Dictionary};
+
+/// Lookup of the heaviest items under a code prefix without scanning the
+/// whole subtree.
+///
+/// Items are laid out in code order, so that the items under any prefix are
+/// a contiguous range, with a segment tree keeping the heaviest item of each
+/// node. It is written after the entries of built dictionaries, see
+/// [`super::open_ranked`].
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RankedIndex {
+    /// Distinct codes, sorted
+    codes: Vec<String>,
+    /// `(index in codes, index in the trie entry)` of each item, in code order
+    items: Vec<(u32, u32)>,
+    weights: Vec<u32>,
+    /// Position of the heaviest item of each node, leaves start at `items.len()`
+    tree: Vec<u32>,
+}
+
+impl RankedIndex {
+    pub fn build(trie: &Dictionary) -> Self {
+        let mut entries: Vec<_> = trie.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut codes = vec![];
+        let mut items = vec![];
+        let mut weights = vec![];
+        for (code, entry) in entries {
+            let code_idx = codes.len() as u32;
+            codes.push(code);
+            for (idx, item) in entry.iter().enumerate() {
+                items.push((code_idx, idx as u32));
+                weights.push(item.weight);
+            }
+        }
+
+        let len = items.len();
+        let mut tree = vec![0; 2 * len];
+        for pos in 0..len {
+            tree[len + pos] = pos as u32;
+        }
+        let mut index = Self {
+            codes,
+            items,
+            weights,
+            tree,
+        };
+        for node in (1..len).rev() {
+            index.tree[node] = index.heavier(index.tree[2 * node], index.tree[2 * node + 1]);
+        }
+        index
+    }
+
+    /// Whether the positions of a deserialized index are in range, so that
+    /// lookups don't panic. Items missing from the trie are skipped anyway.
+    pub(super) fn is_consistent(&self) -> bool {
+        let len = self.items.len();
+        self.weights.len() == len
+            && self.tree.len() == 2 * len
+            && self.tree.iter().all(|pos| (*pos as usize) < len)
+            && self
+                .items
+                .iter()
+                .all(|(code, _)| (*code as usize) < self.codes.len())
+    }
+
+    /// Items with a code starting with `prefix`, heaviest first. Ties keep the
+    /// code order.
+    pub fn iter<'a>(&'a self, trie: &'a Dictionary, prefix: &str) -> RankedIter<'a> {
+        let (start, _, end) = self.range(prefix);
+        self.iter_range(trie, start, end)
+    }
+
+    /// Items with exactly the code `code`, heaviest first.
+    pub fn cursor_exact(&self, code: &str) -> RankedCursor {
+        let (start, exact_end, _) = self.range(code);
+        self.cursor(start, exact_end)
+    }
+
+    /// Items with a code longer than `prefix` and starting with it, heaviest first.
+    pub fn cursor_completions(&self, prefix: &str) -> RankedCursor {
+        let (_, exact_end, end) = self.range(prefix);
+        self.cursor(exact_end, end)
+    }
+
+    /// The item at a position returned by [`RankedCursor::next`].
+    pub fn item<'a>(&self, trie: &'a Dictionary, pos: usize) -> Option<&'a DictItem> {
+        let (code, idx) = self.items[pos];
+        trie.get(&self.codes[code as usize])
+            .and_then(|entry| entry.get(idx as usize))
+    }
+
+    fn iter_range<'a>(&'a self, trie: &'a Dictionary, start: usize, end: usize) -> RankedIter<'a> {
+        RankedIter {
+            index: self,
+            trie,
+            cursor: self.cursor(start, end),
+            seen: HashSet::new(),
+        }
+    }
+
+    fn cursor(&self, start: usize, end: usize) -> RankedCursor {
+        let mut heap = BinaryHeap::new();
+        self.push_range(&mut heap, start, end);
+        RankedCursor { heap }
+    }
+
+    /// Positions of the items with a code starting with `prefix`, split at
+    /// the end of the ones with exactly `prefix`, which sort first.
+    fn range(&self, prefix: &str) -> (usize, usize, usize) {
+        let first = self.codes.partition_point(|code| code.as_str() < prefix);
+        let last = first + self.codes[first..].partition_point(|code| code.starts_with(prefix));
+        let exact_last = if self.codes.get(first).is_some_and(|code| code == prefix) {
+            first + 1
+        } else {
+            first
+        };
+        let position = |code_idx: usize| {
+            self.items
+                .partition_point(|(code, _)| (*code as usize) < code_idx)
+        };
+        (position(first), position(exact_last), position(last))
+    }
+
+    /// The heavier of two positions, the earlier one on ties.
+    fn heavier(&self, a: u32, b: u32) -> u32 {
+        let (weight_a, weight_b) = (self.weights[a as usize], self.weights[b as usize]);
+        if weight_b > weight_a || (weight_b == weight_a && b < a) {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Position of the heaviest item in `start..end`, which must not be empty.
+    fn max_in(&self, start: usize, end: usize) -> u32 {
+        let len = self.items.len();
+        let (mut left, mut right) = (start + len, end + len);
+        let mut best = start as u32;
+        while left < right {
+            if left % 2 == 1 {
+                best = self.heavier(best, self.tree[left]);
+                left += 1;
+            }
+            if right % 2 == 1 {
+                right -= 1;
+                best = self.heavier(best, self.tree[right]);
+            }
+            left /= 2;
+            right /= 2;
+        }
+        best
+    }
+
+    fn push_range(&self, heap: &mut BinaryHeap<Range>, start: usize, end: usize) {
+        if start < end {
+            let pos = self.max_in(start, end);
+            heap.push(Range {
+                weight: self.weights[pos as usize],
+                pos: Reverse(pos),
+                start,
+                end,
+            });
+        }
+    }
+}
+
+/// A range of items keyed by its heaviest one.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Range {
+    weight: u32,
+    pos: Reverse<u32>,
+    start: usize,
+    end: usize,
+}
+
+/// State of a lazy ranked lookup, yielding item positions heaviest first.
+///
+/// It doesn't borrow the index, so that it can live next to an owned one,
+/// e.g. in an `Arc` swapped on reload.
+#[derive(Debug, Default)]
+pub struct RankedCursor {
+    heap: BinaryHeap<Range>,
+}
+
+impl RankedCursor {
+    /// Position of the next heaviest item, to get with [`RankedIndex::item`].
+    pub fn next(&mut self, index: &RankedIndex) -> Option<usize> {
+        let range = self.heap.pop()?;
+        let pos = range.pos.0 as usize;
+        index.push_range(&mut self.heap, range.start, pos);
+        index.push_range(&mut self.heap, pos + 1, range.end);
+        Some(pos)
+    }
+}
+
+/// Lazy iterator of [`RankedIndex::iter`], skipping texts already returned.
+#[derive(Debug)]
+pub struct RankedIter<'a> {
+    index: &'a RankedIndex,
+    trie: &'a Dictionary,
+    cursor: RankedCursor,
+    seen: HashSet<&'a str>,
+}
+
+impl<'a> Iterator for RankedIter<'a> {
+    type Item = &'a DictItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(pos) = self.cursor.next(self.index) {
+            let Some(item) = self.index.item(self.trie, pos) else {
+                continue;
+            };
+            if self.seen.insert(item.text.as_str()) {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranked_order() {
+        let mut trie = Dictionary::new();
+        let mut seed = 7u32;
+        for idx in 0..500 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let code: String = (0..1 + seed % 4)
+                .map(|n| char::from(b'a' + ((seed >> (n * 3)) % 3) as u8))
+                .collect();
+            let item = DictItem {
+                text: format!("w{}", idx % 300),
+                code: code.clone(),
+                weight: (seed >> 16) % 50,
+                comment: None,
+            };
+            match trie.get_mut(&code) {
+                Some(entry) => entry.push(item),
+                None => {
+                    trie.insert(code, vec![item]);
+                }
+            }
+        }
+        let index = RankedIndex::build(&trie);
+
+        for prefix in ["a", "ab", "cab", "bbbb", "d"] {
+            let ranked: Vec<(&str, u32)> = index
+                .iter(&trie, prefix)
+                .map(|item| (item.text.as_str(), item.weight))
+                .collect();
+            let mut expected: Vec<(String, u32)> = vec![];
+            for (_, entry) in trie.iter_prefix(prefix) {
+                for item in entry {
+                    match expected.iter_mut().find(|(text, _)| *text == item.text) {
+                        Some(found) => found.1 = found.1.max(item.weight),
+                        None => expected.push((item.text.clone(), item.weight)),
+                    }
+                }
+            }
+            expected.sort_by_key(|(_, weight)| Reverse(*weight));
+
+            let weights: Vec<u32> = ranked.iter().map(|(_, weight)| *weight).collect();
+            let expected_weights: Vec<u32> = expected.iter().map(|(_, weight)| *weight).collect();
+            assert_eq!(weights, expected_weights, "prefix {}", prefix);
+
+            let count = |mut cursor: RankedCursor| {
+                let mut items = vec![];
+                while let Some(pos) = cursor.next(&index) {
+                    items.push(index.item(&trie, pos).unwrap());
+                }
+                items
+            };
+            let exact = count(index.cursor_exact(prefix));
+            assert!(exact.iter().all(|item| item.code == prefix));
+            assert_eq!(exact.len(), trie.get(prefix).map_or(0, Vec::len));
+            let completions = count(index.cursor_completions(prefix));
+            assert!(completions
+                .iter()
+                .all(|item| item.code.len() > prefix.len()));
+            let texts: HashSet<&str> = exact
+                .iter()
+                .chain(&completions)
+                .map(|item| item.text.as_str())
+                .collect();
+            assert_eq!(texts.len(), ranked.len(), "prefix {}", prefix);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::{fmt, fs, path::Path};
+
+use encoding_rs::{Encoding, UTF_8};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::error::LiushuError;
+
+use super::DictItem;
+
+/// Column of a dictionary source, see [`SourceFormat::with_columns`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    Text,
+    Code,
+    Weight,
+    Comment,
+    /// Ignored
+    Skip,
+}
+
+impl Column {
+    /// Parse a column by name, `-` or `skip` for ignored ones.
+    pub fn from_name(name: &str) -> Result<Self, LiushuError> {
+        match name {
+            "text" => Ok(Self::Text),
+            "code" => Ok(Self::Code),
+            "weight" => Ok(Self::Weight),
+            "comment" => Ok(Self::Comment),
+            "-" | "skip" => Ok(Self::Skip),
+            _ => Err(LiushuError::Config(format!("unknown column {}", name))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Code => "code",
+            Self::Weight => "weight",
+            Self::Comment => "comment",
+            Self::Skip => "-",
+        }
+    }
+}
+
+/// Layout and encoding of a TSV dictionary source. Stored in dictionary
+/// headers as its [`fmt::Display`] form.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct SourceFormat {
+    /// Columns of a source without header row, `None` for sources starting
+    /// with a `text code weight comment` header
+    pub columns: Option<Vec<Column>>,
+    /// Weight of entries without one
+    pub default_weight: u32,
+    pub encoding: &'static Encoding,
+}
+
+impl Default for SourceFormat {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            default_weight: 0,
+            encoding: UTF_8,
+        }
+    }
+}
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.columns {
+            Some(columns) => write!(f, "{}", columns.iter().map(|c| c.name()).join(","))?,
+            None => write!(f, "header")?,
+        }
+        write!(f, ";{};{}", self.encoding.name(), self.default_weight)
+    }
+}
+
+impl From<SourceFormat> for String {
+    fn from(format: SourceFormat) -> Self {
+        format.to_string()
+    }
+}
+
+impl TryFrom<String> for SourceFormat {
+    type Error = LiushuError;
+
+    /// Parse the [`fmt::Display`] form, e.g. `text,code;GBK;0`.
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        let invalid = || LiushuError::Config(format!("invalid source format {}", spec));
+        let mut parts = spec.splitn(3, ';');
+        let (Some(columns), Some(encoding), Some(weight)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let format = Self::default()
+            .with_default_weight(weight.parse().map_err(|_| invalid())?)
+            .with_encoding(encoding)?;
+        match columns {
+            "header" => Ok(format),
+            columns => format.with_columns(columns),
+        }
+    }
+}
+
+impl SourceFormat {
+    /// Read sources without header row, e.g. `text,code,weight`. Sources
+    /// without code column get generated codes.
+    pub fn with_columns(mut self, spec: &str) -> Result<Self, LiushuError> {
+        let columns: Vec<Column> = spec
+            .split(',')
+            .map(|name| Column::from_name(name.trim()))
+            .collect::<Result<_, _>>()?;
+        if !columns.contains(&Column::Text) {
+            return Err(LiushuError::Config(format!(
+                "columns {} have no text column",
+                spec
+            )));
+        }
+        if !columns
+            .iter()
+            .filter(|column| **column != Column::Skip)
+            .all_unique()
+        {
+            return Err(LiushuError::Config(format!(
+                "columns {} repeat a column",
+                spec
+            )));
+        }
+        self.columns = Some(columns);
+        Ok(self)
+    }
+
+    pub fn with_default_weight(mut self, weight: u32) -> Self {
+        self.default_weight = weight;
+        self
+    }
+
+    /// Decode sources by an encoding label, e.g. `gbk` or `utf-16le`.
+    pub fn with_encoding(mut self, label: &str) -> Result<Self, LiushuError> {
+        self.encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| LiushuError::Config(format!("unknown encoding {}", label)))?;
+        Ok(self)
+    }
+
+    /// Entries of a source, with an empty code where it has none.
+    pub(super) fn read(&self, path: &Path) -> Result<Vec<DictItem>, LiushuError> {
+        let bytes = fs::read(path)?;
+        let (content, _, malformed) = self.encoding.decode(&bytes);
+        if malformed {
+            return Err(LiushuError::dict_format(
+                path,
+                None,
+                format!("not valid {}", self.encoding.name()),
+            ));
+        }
+
+        let mut builder = csv::ReaderBuilder::new();
+        builder.delimiter(b'\t').comment(Some(b'#'));
+        let Some(columns) = &self.columns else {
+            return builder
+                .from_reader(content.as_bytes())
+                .deserialize()
+                .map(|result| result.map_err(|err| LiushuError::from_csv(path, err)))
+                .collect();
+        };
+
+        let mut reader = builder
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(content.as_bytes());
+        let mut items = vec![];
+        for record in reader.records() {
+            let record = record.map_err(|err| LiushuError::from_csv(path, err))?;
+            let line = record.position().map(|pos| pos.line());
+            let mut item = DictItem {
+                text: String::new(),
+                code: String::new(),
+                weight: self.default_weight,
+                comment: None,
+            };
+            for (column, field) in columns.iter().zip(record.iter()) {
+                match column {
+                    Column::Text => item.text = field.to_string(),
+                    Column::Code => item.code = field.to_string(),
+                    Column::Weight if !field.is_empty() => {
+                        item.weight = field.parse().map_err(|_| {
+                            LiushuError::dict_format(
+                                path,
+                                line,
+                                format!("invalid weight {}", field),
+                            )
+                        })?;
+                    }
+                    Column::Comment if !field.is_empty() => item.comment = Some(field.to_string()),
+                    _ => {}
+                }
+            }
+            if item.text.is_empty() {
+                return Err(LiushuError::dict_format(path, line, "missing text"));
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
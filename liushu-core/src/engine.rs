@@ -1,38 +1,949 @@
 pub mod candidates;
+pub mod converter;
+pub mod filter;
+pub mod ranking;
+pub mod reverse_lookup;
 pub mod segmentor;
 pub mod translator;
 
-use std::{fs::File, path::Path};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+};
 
-use crate::{dict::Dictionary, error::LiushuError};
+use itertools::Itertools;
 
-use self::{candidates::Candidate, segmentor::Segmentor, translator::Translator};
+use crate::{
+    config::Formula,
+    dict::{
+        self, load_reverse_index, reverse_index_path, DictItem, Dictionary, Encoder, RankedCursor,
+        RankedIndex, ReverseIndex,
+    },
+    error::LiushuError,
+    hmm::decoder::Decoder,
+    user_dict::UserDictionary,
+};
+
+use self::{
+    candidates::Candidate,
+    converter::Converter,
+    filter::{default_filters, Filter},
+    ranking::{RankingPolicy, Tier},
+    reverse_lookup::{ReverseLookup, DEFAULT_PREFIX},
+    segmentor::{segment_by, Segmentor},
+    translator::Translator,
+};
+
+/// Where symbol candidates go when the formula doesn't say, after the best word
+pub const DEFAULT_SYMBOL_POSITION: usize = 1;
 
 pub trait InputMethodEngine {
     fn search(&self, code: &str) -> Result<Vec<Candidate>, LiushuError>;
 }
 
+/// The dictionaries of an engine with their ranking indexes, swapped as a
+/// whole on reload.
 #[derive(Debug, Default)]
+struct Lexicon {
+    /// Each built dictionary with the index written along with it
+    parts: Vec<(Dictionary, RankedIndex)>,
+}
+
+impl Lexicon {
+    fn new(trie: Dictionary) -> Self {
+        let index = RankedIndex::build(&trie);
+        Self {
+            parts: vec![(trie, index)],
+        }
+    }
+
+    fn item(&self, part: usize, pos: usize) -> Option<&DictItem> {
+        let (trie, index) = &self.parts[part];
+        index.item(trie, pos)
+    }
+
+    /// Next position of `cursor` over the dictionary `part` that has an item.
+    fn next(&self, part: usize, cursor: &mut RankedCursor) -> Option<usize> {
+        loop {
+            let pos = cursor.next(&self.parts[part].1)?;
+            if self.item(part, pos).is_some() {
+                return Some(pos);
+            }
+        }
+    }
+
+    /// Candidates of one cursor per dictionary, heaviest first and the
+    /// earlier dictionaries first on ties, keeping the lexicon alive while
+    /// they are iterated.
+    fn candidates<'a>(
+        self: Arc<Self>,
+        mut cursors: Vec<RankedCursor>,
+        code: &'a str,
+    ) -> impl Iterator<Item = Candidate> + 'a {
+        let mut heads: Vec<Option<usize>> = vec![None; cursors.len()];
+        std::iter::from_fn(move || {
+            let mut best: Option<(usize, &DictItem)> = None;
+            for (part, head) in heads.iter_mut().enumerate() {
+                if head.is_none() {
+                    *head = self.next(part, &mut cursors[part]);
+                }
+                let Some(item) = head.and_then(|pos| self.item(part, pos)) else {
+                    continue;
+                };
+                if best.is_none_or(|(_, best)| item.weight > best.weight) {
+                    best = Some((part, item));
+                }
+            }
+            let (part, item) = best?;
+            heads[part] = None;
+            Some(Candidate::from(item).with_code_hint(code))
+        })
+    }
+}
+
+impl Segmentor for Lexicon {
+    fn segment(&self, code: &str) -> Vec<String> {
+        segment_by(code, |rest| {
+            self.parts
+                .iter()
+                .map(|(trie, _)| trie.longest_common_prefix_len(rest))
+                .max()
+                .unwrap_or(0)
+        })
+    }
+}
+
+#[derive(Debug)]
 pub struct Engine {
-    trie: Dictionary,
+    lexicon: RwLock<Arc<Lexicon>>,
+    /// Built dictionaries `lexicon` was loaded from, empty if it was given as is
+    dictionary_paths: Vec<PathBuf>,
+    /// Formula the dictionaries must have been built for, if any
+    formula_id: Option<String>,
+    ranking: RankingPolicy,
+    decoder: Option<Decoder>,
+    user_dict: Option<UserDictionary>,
+    converter: Option<Converter>,
+    /// Whether candidates go through `converter`, can be switched at runtime
+    convert: AtomicBool,
+    /// Whether next words are suggested after a commit, can be switched at runtime
+    predict: AtomicBool,
+    /// Run in order on the translated candidates
+    filters: Vec<Box<dyn Filter>>,
+    /// Emoji and symbol tables, see [`crate::dict::build_symbols`]
+    symbols: Option<Dictionary>,
+    symbol_position: usize,
+    reverse_lookup: Option<ReverseLookup>,
+    /// Generates the codes of learnt phrases, see [`Self::encode`]
+    encoder: Encoder,
+    /// Codes of the characters of the dictionaries, only loaded for `encoder`
+    char_codes: RwLock<ReverseIndex>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            lexicon: RwLock::default(),
+            dictionary_paths: vec![],
+            formula_id: None,
+            ranking: RankingPolicy::default(),
+            decoder: None,
+            user_dict: None,
+            converter: None,
+            convert: AtomicBool::new(false),
+            predict: AtomicBool::new(false),
+            filters: default_filters(),
+            symbols: None,
+            symbol_position: DEFAULT_SYMBOL_POSITION,
+            reverse_lookup: None,
+            encoder: Encoder::default(),
+            char_codes: RwLock::default(),
+        }
+    }
 }
 
 impl Engine {
     pub fn new(dict_path: impl AsRef<Path>) -> Result<Self, LiushuError> {
-        let trie: Dictionary = bincode::deserialize_from(File::open(dict_path)?)?;
+        let dict_path = dict_path.as_ref();
+        let dictionary_paths = vec![dict_path.to_path_buf()];
+        Ok(Self {
+            lexicon: RwLock::new(Arc::new(load_lexicon(&dictionary_paths, None)?)),
+            dictionary_paths,
+            ..Default::default()
+        })
+    }
+
+    pub fn from_dictionary(trie: Dictionary) -> Self {
+        Self {
+            lexicon: RwLock::new(Arc::new(Lexicon::new(trie))),
+            ..Default::default()
+        }
+    }
+
+    /// Load the dictionaries and model of a formula, with paths relative to `data_dir`.
+    pub fn from_formula(
+        formula: &Formula,
+        data_dir: impl AsRef<Path>,
+    ) -> Result<Self, LiushuError> {
+        let data_dir = data_dir.as_ref();
+        if formula.dictionaries.is_empty() {
+            return Err(LiushuError::Config(format!(
+                "formula {} has no dictionaries",
+                formula.id
+            )));
+        }
+
+        let dictionary_paths: Vec<PathBuf> = formula
+            .dictionaries
+            .iter()
+            .map(|dict| data_dir.join(dict))
+            .collect();
+        let lexicon = load_lexicon(&dictionary_paths, Some(&formula.id))?;
+        let symbols = if formula.symbols.is_empty() {
+            None
+        } else {
+            let paths: Vec<PathBuf> = formula.symbols.iter().map(|s| data_dir.join(s)).collect();
+            Some(load_dictionaries(&paths)?)
+        };
+
+        let reverse_lookup = match &formula.reverse_lookup {
+            Some(dict) => {
+                let index = load_reverse_indexes(&dictionary_paths)?;
+                let prefix = formula
+                    .reverse_lookup_prefix
+                    .as_deref()
+                    .unwrap_or(DEFAULT_PREFIX);
+                Some(ReverseLookup::new(
+                    prefix,
+                    dict::load(data_dir.join(dict))?,
+                    index,
+                ))
+            }
+            None => None,
+        };
+
+        let decoder = match (&formula.model, formula.use_hmm) {
+            (Some(model), true) => Some(Decoder::open(data_dir.join(model))?),
+            (None, true) => {
+                return Err(LiushuError::Config(format!(
+                    "formula {} uses hmm but has no model",
+                    formula.id
+                )))
+            }
+            (_, false) => None,
+        };
+
+        let converter = if formula.conversion.is_empty() {
+            None
+        } else {
+            Some(Converter::open(&formula.conversion, data_dir)?)
+        };
+
+        let encoder = Encoder::from_config(&formula.encoder)?;
+        let char_codes = if encoder.is_empty() {
+            ReverseIndex::new()
+        } else {
+            load_reverse_indexes(&dictionary_paths)?
+        };
+
+        let filters = if formula.filters.is_empty() {
+            default_filters()
+        } else {
+            formula
+                .filters
+                .iter()
+                .map(|name| filter::from_name(name))
+                .collect::<Result<_, _>>()?
+        };
+
+        Ok(Self {
+            lexicon: RwLock::new(Arc::new(lexicon)),
+            dictionary_paths,
+            formula_id: Some(formula.id.clone()),
+            ranking: RankingPolicy::from_config(&formula.ranking)?,
+            decoder,
+            user_dict: None,
+            convert: AtomicBool::new(converter.is_some()),
+            converter,
+            predict: AtomicBool::new(formula.prediction.unwrap_or(false)),
+            filters,
+            symbols,
+            symbol_position: formula
+                .symbol_position
+                .map_or(DEFAULT_SYMBOL_POSITION, |pos| pos as usize),
+            reverse_lookup,
+            encoder,
+            char_codes: RwLock::new(char_codes),
+        })
+    }
+
+    pub fn with_decoder(mut self, decoder: Decoder) -> Self {
+        self.decoder = Some(decoder);
+        self
+    }
+
+    pub fn with_user_dict(mut self, user_dict: UserDictionary) -> Self {
+        self.user_dict = Some(user_dict);
+        self
+    }
+
+    /// Convert candidates, e.g. from simplified to traditional Chinese.
+    pub fn with_converter(mut self, converter: Converter) -> Self {
+        self.converter = Some(converter);
+        self.convert = AtomicBool::new(true);
+        self
+    }
+
+    /// Offer emoji and symbols from `symbols` at `position` among the candidates.
+    pub fn with_symbols(mut self, symbols: Dictionary, position: usize) -> Self {
+        self.symbols = Some(symbols);
+        self.symbol_position = position;
+        self
+    }
+
+    pub fn with_reverse_lookup(mut self, reverse_lookup: ReverseLookup) -> Self {
+        self.reverse_lookup = Some(reverse_lookup);
+        self
+    }
 
-        Ok(Self { trie })
+    /// Learn phrases by the codes `encoder` generates from `char_codes`.
+    pub fn with_encoder(mut self, encoder: Encoder, char_codes: ReverseIndex) -> Self {
+        self.encoder = encoder;
+        self.char_codes = RwLock::new(char_codes);
+        self
+    }
+
+    /// Replace the candidate pipeline.
+    pub fn with_filters(mut self, filters: Vec<Box<dyn Filter>>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn with_ranking(mut self, ranking: RankingPolicy) -> Self {
+        self.ranking = ranking;
+        self
+    }
+
+    pub fn load_model(&mut self, model_path: impl AsRef<Path>) -> Result<(), LiushuError> {
+        self.decoder = Some(Decoder::open(model_path)?);
+        Ok(())
+    }
+
+    /// Built dictionaries the engine was loaded from, see [`Self::reload`].
+    pub fn dictionary_paths(&self) -> &[PathBuf] {
+        &self.dictionary_paths
+    }
+
+    /// Swap in new dictionaries. Sessions keep their engine, lookups already
+    /// running finish on the old dictionaries.
+    pub fn replace_dictionary(&self, trie: Dictionary) {
+        let lexicon = Arc::new(Lexicon::new(trie));
+        *self.lexicon.write().unwrap_or_else(PoisonError::into_inner) = lexicon;
+    }
+
+    /// Load the dictionaries again from [`Self::dictionary_paths`], e.g. after
+    /// they were rebuilt, along with their reverse indexes used by the reverse
+    /// lookup and the encoder. On error the current ones are kept.
+    pub fn reload(&self) -> Result<(), LiushuError> {
+        if self.dictionary_paths.is_empty() {
+            return Err(LiushuError::Config(
+                "the engine wasn't loaded from dictionary files".to_string(),
+            ));
+        }
+        let lexicon = load_lexicon(&self.dictionary_paths, self.formula_id.as_deref())?;
+        let index = if self.reverse_lookup.is_some() || !self.encoder.is_empty() {
+            Some(load_reverse_indexes(&self.dictionary_paths)?)
+        } else {
+            None
+        };
+
+        if let Some(index) = index {
+            if let Some(reverse_lookup) = &self.reverse_lookup {
+                reverse_lookup.replace_index(index.clone());
+            }
+            if !self.encoder.is_empty() {
+                *self
+                    .char_codes
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner) = index;
+            }
+        }
+        *self.lexicon.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(lexicon);
+        Ok(())
+    }
+
+    pub fn decoder(&self) -> Option<&Decoder> {
+        self.decoder.as_ref()
+    }
+
+    pub fn user_dict(&self) -> Option<&UserDictionary> {
+        self.user_dict.as_ref()
+    }
+
+    pub fn ranking(&self) -> &RankingPolicy {
+        &self.ranking
+    }
+
+    pub fn has_symbols(&self) -> bool {
+        self.symbols.is_some()
+    }
+
+    /// Whether `ch` opens a special input rather than being punctuation, i.e.
+    /// `/` for symbols or the reverse lookup prefix.
+    pub fn starts_input(&self, ch: char) -> bool {
+        (ch == '/' && self.has_symbols())
+            || self
+                .reverse_lookup
+                .as_ref()
+                .is_some_and(|lookup| lookup.prefix().starts_with(ch))
+    }
+
+    pub fn converter(&self) -> Option<&Converter> {
+        self.converter.as_ref()
+    }
+
+    /// Turn the conversion of candidates on or off, no-op without a converter.
+    pub fn set_conversion(&self, enabled: bool) {
+        self.convert.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn conversion_enabled(&self) -> bool {
+        self.converter.is_some() && self.convert.load(Ordering::Relaxed)
+    }
+
+    /// Turn next-word prediction on or off.
+    pub fn set_prediction(&self, enabled: bool) {
+        self.predict.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn prediction_enabled(&self) -> bool {
+        self.predict.load(Ordering::Relaxed)
+    }
+
+    /// Convert the whole input into a sentence with the HMM model, if one is loaded.
+    pub fn sentence(&self, code: &str) -> Result<Option<String>, LiushuError> {
+        let sentences = self.sentences(code, 1)?;
+        Ok(sentences.into_iter().next().map(|c| c.text))
+    }
+
+    /// The `top_k` most probable sentences for the whole input, best first.
+    pub fn sentences(&self, code: &str, top_k: usize) -> Result<Vec<Candidate>, LiushuError> {
+        let Some(decoder) = &self.decoder else {
+            return Ok(vec![]);
+        };
+        if code.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let sentences = decoder.decode_top_k(&self.segment(code), top_k)?;
+        let candidates = sentences
+            .into_iter()
+            .filter(|text| !text.is_empty())
+            .map(|text| Candidate {
+                text,
+                code: code.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        Ok(self.apply_filters(code, candidates))
+    }
+
+    /// Feed a committed candidate back so that it ranks higher next time.
+    /// Words found by a reverse lookup are learnt under their primary code,
+    /// or not at all if they have none.
+    pub fn learn(&self, code: &str, text: &str) -> Result<(), LiushuError> {
+        let Some(user_dict) = &self.user_dict else {
+            return Ok(());
+        };
+        match &self.reverse_lookup {
+            Some(reverse_lookup) if code.starts_with(reverse_lookup.prefix()) => {
+                match reverse_lookup.primary_code(text) {
+                    Some(code) => user_dict.learn(&code, text),
+                    None => Ok(()),
+                }
+            }
+            _ => user_dict.learn(code, text),
+        }
+    }
+
+    /// Code of a new phrase by the encoder rules of the formula, `None` if it
+    /// has none or they don't apply.
+    pub fn encode(&self, text: &str) -> Option<String> {
+        if self.encoder.is_empty() {
+            return None;
+        }
+        let char_codes = self
+            .char_codes
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.encoder.encode(text, &char_codes)
+    }
+
+    /// Feed a word committed right after `prev` back so that it is predicted
+    /// next time.
+    pub fn learn_next(&self, prev: &str, text: &str) -> Result<(), LiushuError> {
+        match &self.user_dict {
+            Some(user_dict) => user_dict.learn_next(prev, text),
+            None => Ok(()),
+        }
+    }
+
+    /// Up to `limit` words likely to follow the committed `text`, the ones the
+    /// user wrote after it first, then the bigrams of the HMM model. Empty
+    /// when prediction is off.
+    pub fn predict(&self, text: &str, limit: usize) -> Result<Vec<Candidate>, LiushuError> {
+        if !self.prediction_enabled() || text.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut words: Vec<String> = match &self.user_dict {
+            Some(user_dict) => user_dict
+                .next_words(text)
+                .into_iter()
+                .map(|(word, _)| word)
+                .collect(),
+            None => vec![],
+        };
+        if let Some(decoder) = &self.decoder {
+            let mut predicted = decoder.predict(text, limit)?;
+            // Phrases made up on commit aren't words of the model, their last char is
+            if predicted.is_empty() {
+                if let Some((idx, _)) = text.char_indices().next_back().filter(|(idx, _)| *idx > 0)
+                {
+                    predicted = decoder.predict(&text[idx..], limit)?;
+                }
+            }
+            words.extend(predicted.into_iter().map(|(word, _)| word));
+        }
+
+        let candidates = words
+            .into_iter()
+            .unique()
+            .take(limit)
+            .map(|text| Candidate {
+                text,
+                ..Default::default()
+            })
+            .collect();
+        Ok(self.apply_filters("", candidates))
+    }
+
+    /// Candidates of the dictionaries under `code` in the order of the
+    /// ranking policy, found lazily so that paging doesn't rank the whole
+    /// subtree.
+    pub fn iter_candidates<'a>(&'a self, code: &'a str) -> impl Iterator<Item = Candidate> + 'a {
+        let mut groups: Vec<(Tier, Box<dyn Iterator<Item = Candidate> + 'a>)> = vec![];
+        if !code.is_empty() {
+            let lexicon = self.lexicon();
+            let exact = lexicon
+                .parts
+                .iter()
+                .map(|(_, index)| index.cursor_exact(code))
+                .collect();
+            let completions = lexicon
+                .parts
+                .iter()
+                .map(|(_, index)| index.cursor_completions(code))
+                .collect();
+            groups.push((
+                Tier::Exact,
+                Box::new(lexicon.clone().candidates(exact, code)),
+            ));
+            groups.push((
+                Tier::Completion,
+                Box::new(lexicon.candidates(completions, code)),
+            ));
+        }
+        self.ranking.arrange(groups)
+    }
+
+    /// Like [`InputMethodEngine::search`], but only ranks the `limit` best
+    /// dictionary candidates.
+    pub fn search_top(&self, code: &str, limit: usize) -> Result<Vec<Candidate>, LiushuError> {
+        Ok(self.search_top_with_more(code, limit)?.0)
+    }
+
+    /// Like [`Self::search_top`], also telling whether the dictionaries have
+    /// candidates beyond `limit`.
+    pub fn search_top_with_more(
+        &self,
+        code: &str,
+        limit: usize,
+    ) -> Result<(Vec<Candidate>, bool), LiushuError> {
+        if let Some(candidates) = self
+            .reverse_lookup
+            .as_ref()
+            .and_then(|lookup| lookup.lookup(code))
+        {
+            return Ok((self.apply_filters(code, candidates), false));
+        }
+
+        let (mut candidates, more) = if code.starts_with('/') {
+            (vec![], false)
+        } else {
+            let mut iter = self.iter_candidates(code);
+            let candidates: Vec<Candidate> = iter.by_ref().take(limit).collect();
+            (candidates, iter.next().is_some())
+        };
+        let symbols = self.symbols(code);
+        if !symbols.is_empty() {
+            let position = self.symbol_position.min(candidates.len());
+            candidates.splice(position..position, symbols);
+        }
+        Ok((self.apply_filters(code, candidates), more))
+    }
+
+    /// Dictionary candidates with exactly the code `code`, heaviest first.
+    pub fn search_exact(&self, code: &str) -> Vec<Candidate> {
+        if code.is_empty() {
+            return vec![];
+        }
+        let lexicon = self.lexicon();
+        let cursors = lexicon
+            .parts
+            .iter()
+            .map(|(_, index)| index.cursor_exact(code))
+            .collect();
+        let candidates = lexicon
+            .candidates(cursors, code)
+            .unique_by(|c| c.text.clone())
+            .collect();
+        self.apply_filters(code, candidates)
+    }
+
+    /// Symbols of a keyword, or of all tables starting with a `/` code.
+    fn symbols(&self, code: &str) -> Vec<Candidate> {
+        let Some(symbols) = &self.symbols else {
+            return vec![];
+        };
+        if code.starts_with('/') {
+            return symbols.translate(code);
+        }
+        self.keyword_symbols(code)
+    }
+
+    /// Symbols of exactly `keyword`, best first, e.g. the emoji of a word.
+    pub fn keyword_symbols(&self, keyword: &str) -> Vec<Candidate> {
+        self.symbols
+            .as_ref()
+            .and_then(|symbols| symbols.get(keyword))
+            .map(|items| items.iter().map(Candidate::from).collect())
+            .unwrap_or_default()
+    }
+
+    fn lexicon(&self) -> Arc<Lexicon> {
+        self.lexicon
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn apply_filters(&self, code: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        self.filters.iter().fold(candidates, |candidates, filter| {
+            filter.filter(self, code, candidates)
+        })
     }
 }
 
+/// Load and merge dictionaries, e.g. symbol tables.
+fn load_dictionaries(paths: &[PathBuf]) -> Result<Dictionary, LiushuError> {
+    let mut trie = Dictionary::new();
+    for dict in paths {
+        for (code, items) in dict::load(dict)? {
+            match trie.get_mut(&code) {
+                Some(entry) => entry.extend(items),
+                None => {
+                    trie.insert(code, items);
+                }
+            }
+        }
+    }
+    Ok(trie)
+}
+
+/// Load dictionaries with their ranking indexes, checking that they were
+/// built for the formula `formula_id` if given.
+fn load_lexicon(paths: &[PathBuf], formula_id: Option<&str>) -> Result<Lexicon, LiushuError> {
+    let mut parts = vec![];
+    for dict in paths {
+        let (header, trie, index) = dict::open_ranked(dict)?;
+        if let (Some(expected), Some(formula)) = (formula_id, &header.formula) {
+            if formula.id != expected {
+                return Err(LiushuError::Config(format!(
+                    "dictionary {} was built for formula {}, not {}",
+                    dict.display(),
+                    formula.id,
+                    expected
+                )));
+            }
+        }
+        parts.push((trie, index));
+    }
+    Ok(Lexicon { parts })
+}
+
+/// Merge the reverse indexes built next to dictionaries.
+fn load_reverse_indexes(paths: &[PathBuf]) -> Result<ReverseIndex, LiushuError> {
+    let mut index = ReverseIndex::new();
+    for path in paths {
+        for (text, codes) in load_reverse_index(reverse_index_path(path))? {
+            let known = index.entry(text).or_default();
+            for code in codes {
+                if !known.contains(&code) {
+                    known.push(code);
+                }
+            }
+        }
+    }
+    Ok(index)
+}
+
 impl InputMethodEngine for Engine {
     fn search(&self, code: &str) -> Result<Vec<Candidate>, LiushuError> {
-        Ok(self.trie.translate(code))
+        self.search_top(code, usize::MAX)
     }
 }
 
 impl Segmentor for Engine {
     fn segment(&self, code: &str) -> Vec<String> {
-        self.trie.segment(code)
+        self.lexicon().segment(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::dict::build;
+    use crate::session::{Key, Session};
+    use crate::test_util::TempDir;
+
+    /// Build `{name}.trie` in `dir` from items of text, code and weight.
+    fn build_dict(dir: &Path, name: &str, items: &[(&str, &str, u32)]) -> PathBuf {
+        let source = dir.join(format!("{}.tsv", name));
+        let mut content = "text\tcode\tweight\tcomment\n".to_string();
+        for (text, code, weight) in items {
+            content.push_str(&format!("{}\t{}\t{}\t\n", text, code, weight));
+        }
+        fs::write(&source, content).unwrap();
+        let output = dir.join(format!("{}.trie", name));
+        build(&[source], &output).unwrap();
+        output
+    }
+
+    fn texts(candidates: Vec<Candidate>) -> Vec<String> {
+        candidates.into_iter().map(|c| c.text).collect()
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = TempDir::new("reload");
+        let path = build_dict(&dir, "dict", &[("你", "ni", 100), ("泥", "ni", 50)]);
+        let engine = Engine::new(&path).unwrap();
+        assert_eq!(texts(engine.search("ni").unwrap()), ["你", "泥"]);
+
+        build_dict(&dir, "dict", &[("尼", "ni", 100)]);
+        engine.reload().unwrap();
+        assert_eq!(texts(engine.search("ni").unwrap()), ["尼"]);
+
+        // a broken dictionary keeps the loaded one
+        fs::write(&path, "garbage").unwrap();
+        assert!(engine.reload().is_err());
+        assert_eq!(texts(engine.search("ni").unwrap()), ["尼"]);
+    }
+
+    #[test]
+    fn test_reload_reverse_lookup() {
+        let dir = TempDir::new("reload-reverse");
+        build_dict(&dir, "pinyin", &[("尼", "ni", 100)]);
+        build_dict(&dir, "shape", &[("尼", "nx", 10)]);
+        let formula = Formula {
+            id: "shape".to_string(),
+            dictionaries: vec!["shape.trie".to_string()],
+            reverse_lookup: Some("pinyin.trie".to_string()),
+            ..Default::default()
+        };
+        let engine = Engine::from_formula(&formula, &*dir).unwrap();
+        let comment = |engine: &Engine| engine.search("`ni").unwrap()[0].comment.clone();
+        assert_eq!(comment(&engine).as_deref(), Some("nx"));
+
+        build_dict(&dir, "shape", &[("尼", "ny", 10)]);
+        engine.reload().unwrap();
+        assert_eq!(comment(&engine).as_deref(), Some("ny"));
+    }
+
+    #[test]
+    fn test_replace_dictionary() {
+        let engine = Engine::from_dictionary(Dictionary::new());
+        // there are no files to load it from again
+        assert!(engine.reload().is_err());
+
+        let mut trie = Dictionary::new();
+        let items = [("你", 100), ("泥", 50)]
+            .into_iter()
+            .map(|(text, weight)| DictItem {
+                text: text.to_string(),
+                code: "ni".to_string(),
+                weight,
+                comment: None,
+            })
+            .collect();
+        trie.insert("ni", items);
+        let mut running = engine.iter_candidates("ni");
+        engine.replace_dictionary(trie);
+        // lookups already running finish on the old dictionaries
+        assert_eq!(running.next(), None);
+        assert_eq!(texts(engine.search("ni").unwrap()), ["你", "泥"]);
+    }
+
+    #[test]
+    fn test_conversion() {
+        let dir = TempDir::new("conversion");
+        build_dict(
+            &dir,
+            "dict",
+            &[
+                ("你", "ni", 100),
+                ("泥", "ni", 50),
+                ("拟", "ni", 30),
+                ("你好", "nihao", 80),
+            ],
+        );
+        fs::write(dir.join("STPhrases.txt"), "你好\t妳好 你好\n").unwrap();
+        fs::write(dir.join("STCharacters.txt"), "泥\t泥\n拟\t擬\n").unwrap();
+        let formula = Formula {
+            id: "sunman".to_string(),
+            dictionaries: vec!["dict.trie".to_string()],
+            conversion: vec![vec![
+                "STPhrases.txt".to_string(),
+                "STCharacters.txt".to_string(),
+            ]],
+            ..Default::default()
+        };
+        let engine = Engine::from_formula(&formula, &*dir).unwrap();
+        assert!(engine.conversion_enabled());
+
+        let converted: Vec<_> = engine
+            .search("ni")
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.text, c.comment))
+            .collect();
+        let expected = [
+            ("你", None),
+            ("泥", None),
+            ("擬", Some("拟")),
+            ("妳好", Some("你好")),
+        ]
+        .map(|(text, comment)| (text.to_string(), comment.map(str::to_string)));
+        assert_eq!(converted, expected);
+
+        engine.set_conversion(false);
+        assert_eq!(engine.search("nihao").unwrap()[0].text, "你好");
+    }
+
+    #[test]
+    fn test_reverse_lookup() {
+        let dir = TempDir::new("reverse");
+        build_dict(
+            &dir,
+            "shape",
+            &[("你", "wq", 100), ("你", "wqiy", 10), ("泥", "iz", 50)],
+        );
+        build_dict(
+            &dir,
+            "pinyin",
+            &[("你", "ni", 100), ("泥", "ni", 50), ("你好", "nihao", 80)],
+        );
+        let formula = Formula {
+            id: "shape".to_string(),
+            dictionaries: vec!["shape.trie".to_string()],
+            reverse_lookup: Some("pinyin.trie".to_string()),
+            ..Default::default()
+        };
+        let engine = Arc::new(Engine::from_formula(&formula, &*dir).unwrap());
+        let comments: Vec<_> = engine
+            .search("`ni")
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.text, c.comment))
+            .collect();
+        let expected = [("你", Some("wq wqiy")), ("泥", Some("iz")), ("你好", None)]
+            .map(|(text, comment)| (text.to_string(), comment.map(str::to_string)));
+        assert_eq!(comments, expected);
+
+        // the prefix starts a composition instead of being punctuation
+        assert!(engine.starts_input('`'));
+        let mut session = Session::new(engine);
+        assert!(session.process_key(Key::Char('`')).unwrap().handled);
+        session.input("ni").unwrap();
+        assert_eq!(session.state().candidates[0].text, "你");
+    }
+
+    #[test]
+    fn test_several_dictionaries() {
+        let dir = TempDir::new("several");
+        build_dict(
+            &dir,
+            "words",
+            &[("你", "ni", 100), ("拟", "ni", 30), ("你好", "nihao", 80)],
+        );
+        build_dict(&dir, "extra", &[("泥", "ni", 50), ("好", "hao", 90)]);
+        let formula = Formula {
+            id: "pinyin".to_string(),
+            dictionaries: vec!["words.trie".to_string(), "extra.trie".to_string()],
+            ..Default::default()
+        };
+        let engine = Engine::from_formula(&formula, &*dir).unwrap();
+        assert_eq!(
+            texts(engine.search("ni").unwrap()),
+            ["你", "泥", "拟", "你好"]
+        );
+        assert_eq!(texts(engine.search("hao").unwrap()), ["好"]);
+
+        let (candidates, more) = engine.search_top_with_more("ni", 2).unwrap();
+        assert_eq!(texts(candidates), ["你", "泥"]);
+        assert!(more);
+        let (candidates, more) = engine.search_top_with_more("ni", 4).unwrap();
+        assert_eq!(candidates.len(), 4);
+        assert!(!more);
+
+        // symbols go after the best word and don't count towards the limit
+        let mut symbols = Dictionary::new();
+        symbols.insert(
+            "ni",
+            vec![DictItem {
+                text: "👋".to_string(),
+                code: "ni".to_string(),
+                weight: 1,
+                comment: None,
+            }],
+        );
+        let engine = engine.with_symbols(symbols, DEFAULT_SYMBOL_POSITION);
+        let (candidates, more) = engine.search_top_with_more("ni", 2).unwrap();
+        assert_eq!(texts(candidates), ["你", "👋", "泥"]);
+        assert!(more);
+    }
+
+    #[test]
+    fn test_predict() {
+        let dir = TempDir::new("predict");
+        let engine = Engine::from_dictionary(Dictionary::new())
+            .with_user_dict(UserDictionary::open(dir.join("user.redb")).unwrap());
+        engine.learn_next("你", "拟").unwrap();
+        engine.learn_next("你", "好").unwrap();
+        engine.learn_next("你", "好").unwrap();
+        // off by default
+        assert!(engine.predict("你", 5).unwrap().is_empty());
+
+        engine.set_prediction(true);
+        assert_eq!(texts(engine.predict("你", 5).unwrap()), ["好", "拟"]);
+        assert_eq!(texts(engine.predict("你", 1).unwrap()), ["好"]);
+        assert!(engine.predict("拟", 5).unwrap().is_empty());
+        assert!(engine.predict("", 5).unwrap().is_empty());
     }
 }
@@ -1,7 +1,36 @@
-#[derive(Debug, Default, Clone)]
+use crate::dict::DictItem;
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Candidate {
     pub text: String,
     pub code: String,
     pub comment: Option<String>,
     pub weight: u32,
+    /// The rest of `code` after the typed input, e.g. `xy` for `abxy` typed
+    /// as `ab`
+    pub code_hint: Option<String>,
+}
+
+impl Candidate {
+    /// Set the code hint of a candidate found by typing `input`.
+    pub fn with_code_hint(mut self, input: &str) -> Self {
+        self.code_hint = self
+            .code
+            .strip_prefix(input)
+            .filter(|rest| !rest.is_empty())
+            .map(str::to_string);
+        self
+    }
+}
+
+impl From<&DictItem> for Candidate {
+    fn from(item: &DictItem) -> Self {
+        Self {
+            text: item.text.clone(),
+            code: item.code.clone(),
+            comment: item.comment.clone(),
+            weight: item.weight,
+            code_hint: None,
+        }
+    }
 }
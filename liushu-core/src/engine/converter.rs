@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::error::LiushuError;
+
+/// Mapping of one OpenCC text dictionary, e.g. `STPhrases.txt`.
+///
+/// Each line is `key<TAB>value [value...]`, the first value is used.
+#[derive(Debug, Default, Clone)]
+pub struct ConversionTable {
+    map: HashMap<String, String>,
+    /// Longest key, in chars
+    max_len: usize,
+}
+
+impl ConversionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LiushuError> {
+        let mut table = Self::new();
+        table.extend_from_file(path)?;
+        Ok(table)
+    }
+
+    /// Add the entries of another OpenCC file, e.g. characters on top of phrases.
+    pub fn extend_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), LiushuError> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some((key, values)) = line.split_once('\t') else {
+                return Err(LiushuError::dict_format(
+                    path,
+                    Some(idx as u64 + 1),
+                    "expect key and values separated by a tab",
+                ));
+            };
+            let Some(value) = values.split_whitespace().next() else {
+                return Err(LiushuError::dict_format(
+                    path,
+                    Some(idx as u64 + 1),
+                    "missing value",
+                ));
+            };
+            // Phrase tables come first, don't let character tables override them
+            if !self.map.contains_key(key) {
+                self.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.max_len = self.max_len.max(key.chars().count());
+        self.map.insert(key.to_string(), value.to_string());
+    }
+
+    /// Convert `text` with forward maximum matching, chars without an entry
+    /// are kept as is.
+    pub fn convert(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = String::with_capacity(text.len());
+        let mut start = 0;
+        while start < chars.len() {
+            let max_end = (start + self.max_len).min(chars.len());
+            let matched = (start + 1..=max_end).rev().find_map(|end| {
+                let key: String = chars[start..end].iter().collect();
+                self.map.get(&key).map(|value| (end, value))
+            });
+            match matched {
+                Some((end, value)) => {
+                    result.push_str(value);
+                    start = end;
+                }
+                None => {
+                    result.push(chars[start]);
+                    start += 1;
+                }
+            }
+        }
+        result
+    }
+}
+
+/// A chain of conversion tables applied one after another, like an OpenCC
+/// config, e.g. `STPhrases.txt` and `STCharacters.txt` then `TWVariants.txt`
+/// for simplified to Taiwan traditional.
+#[derive(Debug, Default, Clone)]
+pub struct Converter {
+    steps: Vec<ConversionTable>,
+}
+
+impl Converter {
+    pub fn new(steps: Vec<ConversionTable>) -> Self {
+        Self { steps }
+    }
+
+    /// Load each step from its OpenCC files, relative to `data_dir`.
+    pub fn open(steps: &[Vec<String>], data_dir: impl AsRef<Path>) -> Result<Self, LiushuError> {
+        let data_dir = data_dir.as_ref();
+        let mut tables = Vec::with_capacity(steps.len());
+        for files in steps {
+            let mut table = ConversionTable::new();
+            for file in files {
+                table.extend_from_file(data_dir.join(file))?;
+            }
+            tables.push(table);
+        }
+        Ok(Self::new(tables))
+    }
+
+    pub fn convert(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for step in &self.steps {
+            text = step.convert(&text);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let mut phrases = ConversionTable::new();
+        phrases.insert("头发", "頭髮");
+        phrases.insert("头", "頭");
+        phrases.insert("发", "發");
+        phrases.insert("里", "裡");
+
+        assert_eq!(phrases.convert("头发"), "頭髮");
+        assert_eq!(phrases.convert("发头a"), "發頭a");
+
+        let mut variants = ConversionTable::new();
+        variants.insert("裡", "裏");
+        let converter = Converter::new(vec![phrases, variants]);
+        assert_eq!(converter.convert("这里"), "这裏");
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use encoding_rs::GBK;
+
+use crate::error::LiushuError;
+
+use super::{candidates::Candidate, Engine};
+
+/// A step of the candidate pipeline run after translation.
+///
+/// Filters get the engine so that they can use its user dictionary and
+/// runtime switches.
+pub trait Filter: Debug + Send + Sync {
+    fn filter(&self, engine: &Engine, code: &str, candidates: Vec<Candidate>) -> Vec<Candidate>;
+}
+
+/// Build a filter by the name used in `Formula::filters`.
+pub fn from_name(name: &str) -> Result<Box<dyn Filter>, LiushuError> {
+    let filter: Box<dyn Filter> = match name {
+        "dedupe" => Box::new(Dedupe),
+        "gb2312" => Box::new(Gb2312),
+        "conversion" => Box::new(Conversion),
+        "history" => Box::new(History),
+        "emoji" => Box::new(Emoji),
+        _ => return Err(LiushuError::Config(format!("unknown filter {}", name))),
+    };
+    Ok(filter)
+}
+
+/// Filters of engines not configured otherwise.
+pub fn default_filters() -> Vec<Box<dyn Filter>> {
+    vec![Box::new(History), Box::new(Conversion)]
+}
+
+/// Keep the first candidate of each text.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Dedupe;
+
+impl Filter for Dedupe {
+    fn filter(&self, _engine: &Engine, _code: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        let mut seen = HashSet::new();
+        candidates
+            .into_iter()
+            .filter(|c| seen.insert(c.text.clone()))
+            .collect()
+    }
+}
+
+/// Drop candidates with chars outside of GB2312, mostly rare and traditional
+/// hanzi.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Gb2312;
+
+impl Gb2312 {
+    pub fn contains(ch: char) -> bool {
+        if ch.is_ascii() {
+            return true;
+        }
+        let mut buf = [0; 4];
+        let (bytes, _, unmappable) = GBK.encode(ch.encode_utf8(&mut buf));
+        // GB2312 is the part of GBK with both bytes in 0xA1..=0xFE
+        !unmappable && bytes.len() == 2 && bytes.iter().all(|b| (0xa1..=0xfe).contains(b))
+    }
+}
+
+impl Filter for Gb2312 {
+    fn filter(&self, _engine: &Engine, _code: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        candidates
+            .into_iter()
+            .filter(|c| c.text.chars().all(Self::contains))
+            .collect()
+    }
+}
+
+/// Convert candidates with the engine's converter, if it is enabled. The
+/// original text goes before the comment.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Conversion;
+
+impl Filter for Conversion {
+    fn filter(&self, engine: &Engine, code: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        let Some(converter) = engine.converter().filter(|_| engine.conversion_enabled()) else {
+            return candidates;
+        };
+        let mut candidates = candidates;
+        for candidate in candidates.iter_mut() {
+            let converted = converter.convert(&candidate.text);
+            if converted != candidate.text {
+                let original = std::mem::replace(&mut candidate.text, converted);
+                // Keep comments like the codes of a reverse lookup
+                candidate.comment = Some(match candidate.comment.take() {
+                    Some(comment) => format!("{} {}", original, comment),
+                    None => original,
+                });
+            }
+        }
+        Dedupe.filter(engine, code, candidates)
+    }
+}
+
+/// Rerank with the engine's user dictionary, see
+/// [`crate::user_dict::UserDictionary::rerank`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct History;
+
+impl Filter for History {
+    fn filter(&self, engine: &Engine, code: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        let mut candidates = candidates;
+        if let Some(user_dict) = engine.user_dict() {
+            user_dict.rerank(code, &mut candidates);
+        }
+        candidates
+    }
+}
+
+/// Insert emoji after the candidates they stand for, e.g. 😄 after 笑, from
+/// the symbols of the engine with the candidate as keyword, see
+/// [`crate::dict::build_symbols`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Emoji;
+
+impl Filter for Emoji {
+    fn filter(&self, engine: &Engine, _code: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        let mut result = Vec::with_capacity(candidates.len());
+        for candidate in candidates {
+            let emoji = engine.keyword_symbols(&candidate.text);
+            let code = candidate.code.clone();
+            result.push(candidate);
+            result.extend(emoji.into_iter().map(|emoji| Candidate {
+                code: code.clone(),
+                ..emoji
+            }));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dict::{DictItem, Dictionary};
+    use crate::engine::converter::{ConversionTable, Converter};
+
+    fn candidates(texts: &[&str]) -> Vec<Candidate> {
+        texts
+            .iter()
+            .map(|text| Candidate {
+                text: text.to_string(),
+                code: "xiao".to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn texts(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_filters() {
+        let engine = Engine::default();
+
+        let result = Dedupe.filter(&engine, "xiao", candidates(&["笑", "小", "笑"]));
+        assert_eq!(texts(&result), vec!["笑", "小"]);
+
+        let result = Gb2312.filter(&engine, "xiao", candidates(&["笑", "嘯", "小a"]));
+        assert_eq!(texts(&result), vec!["笑", "小a"]);
+
+        let mut symbols = Dictionary::new();
+        symbols.insert(
+            "笑",
+            ["😄", "😂"]
+                .iter()
+                .map(|emoji| DictItem {
+                    text: emoji.to_string(),
+                    code: "笑".to_string(),
+                    weight: 0,
+                    comment: None,
+                })
+                .collect(),
+        );
+        let with_symbols = Engine::default().with_symbols(symbols, 1);
+        let result = Emoji.filter(&with_symbols, "xiao", candidates(&["笑", "小"]));
+        assert_eq!(texts(&result), vec!["笑", "😄", "😂", "小"]);
+        assert!(result.iter().all(|c| c.code == "xiao"));
+
+        let mut table = ConversionTable::new();
+        table.insert("笑", "咲");
+        let engine = Engine::default().with_converter(Converter::new(vec![table]));
+        let mut input = candidates(&["笑", "小"]);
+        input[0].comment = Some("xiao".to_string());
+        let result = Conversion.filter(&engine, "xiao", input);
+        assert_eq!(texts(&result), vec!["咲", "小"]);
+        assert_eq!(result[0].comment.as_deref(), Some("笑 xiao"));
+        assert_eq!(result[1].comment, None);
+    }
+}
@@ -0,0 +1,204 @@
+use itertools::Itertools;
+
+use crate::error::LiushuError;
+
+use super::candidates::Candidate;
+
+/// Kind of match of a candidate against the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    /// Words with exactly the typed code
+    Exact,
+    /// Words with a longer code starting with the input
+    Completion,
+    /// Words matching only part of the input, e.g. its leading segments
+    Fuzzy,
+    /// Sentences predicted by the HMM model
+    Predicted,
+}
+
+impl Tier {
+    /// Parse a tier by the name used in `Formula::ranking`.
+    pub fn from_name(name: &str) -> Result<Self, LiushuError> {
+        match name {
+            "exact" => Ok(Self::Exact),
+            "completion" => Ok(Self::Completion),
+            "fuzzy" => Ok(Self::Fuzzy),
+            "predicted" => Ok(Self::Predicted),
+            _ => Err(LiushuError::Config(format!(
+                "unknown ranking tier {}",
+                name
+            ))),
+        }
+    }
+
+    /// Tier of a dictionary candidate found by typing `input`. Candidates of
+    /// codes unrelated to the input, e.g. from a reverse lookup, count as exact.
+    pub fn of(input: &str, candidate: &Candidate) -> Self {
+        if candidate.code.len() > input.len() && candidate.code.starts_with(input) {
+            Self::Completion
+        } else if candidate.code.len() < input.len() && input.starts_with(&candidate.code) {
+            Self::Fuzzy
+        } else {
+            Self::Exact
+        }
+    }
+}
+
+/// Order of candidates by tier, then by weight within a tier.
+///
+/// Tiers holding several kinds merge them by weight, each scaled by a
+/// percentage, so that e.g. completions can compete with exact matches at
+/// half their weight. Kinds left out of the policy are dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankingPolicy {
+    tiers: Vec<Vec<(Tier, u32)>>,
+}
+
+impl Default for RankingPolicy {
+    fn default() -> Self {
+        Self::new(vec![
+            vec![(Tier::Exact, 100)],
+            vec![(Tier::Predicted, 100)],
+            vec![(Tier::Completion, 100)],
+            vec![(Tier::Fuzzy, 100)],
+        ])
+    }
+}
+
+impl RankingPolicy {
+    /// `tiers` from first to last, each with the weight percentage of its kinds.
+    pub fn new(tiers: Vec<Vec<(Tier, u32)>>) -> Self {
+        Self { tiers }
+    }
+
+    /// Parse `Formula::ranking`, e.g. `[["exact", "completion:50"], ["predicted"]]`.
+    /// Empty means the default policy.
+    pub fn from_config(tiers: &[Vec<String>]) -> Result<Self, LiushuError> {
+        if tiers.is_empty() {
+            return Ok(Self::default());
+        }
+        let tiers = tiers
+            .iter()
+            .map(|tier| {
+                tier.iter()
+                    .map(|name| match name.split_once(':') {
+                        Some((name, percent)) => {
+                            let percent = percent.parse().map_err(|_| {
+                                LiushuError::Config(format!(
+                                    "invalid weight percentage of ranking tier {}",
+                                    name
+                                ))
+                            })?;
+                            Ok((Tier::from_name(name)?, percent))
+                        }
+                        None => Ok((Tier::from_name(name)?, 100)),
+                    })
+                    .collect::<Result<Vec<_>, LiushuError>>()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(tiers))
+    }
+
+    /// Whether candidates of `tier` are shown at all.
+    pub fn contains(&self, tier: Tier) -> bool {
+        self.position(tier).is_some()
+    }
+
+    /// Index of the policy tier of `tier` and its weight percentage.
+    fn position(&self, tier: Tier) -> Option<(usize, u32)> {
+        self.tiers.iter().enumerate().find_map(|(idx, kinds)| {
+            kinds
+                .iter()
+                .find(|(kind, _)| *kind == tier)
+                .map(|(_, percent)| (idx, *percent))
+        })
+    }
+
+    /// Lay out groups of candidates by tier. Groups of the same tier are
+    /// merged by scaled weight, keeping the order within each group, and
+    /// only the first candidate of each text is kept.
+    pub fn arrange<I>(&self, groups: Vec<(Tier, I)>) -> impl Iterator<Item = Candidate>
+    where
+        I: IntoIterator<Item = Candidate>,
+    {
+        let mut tiers: Vec<Vec<_>> = self.tiers.iter().map(|_| vec![]).collect();
+        for (idx, (tier, candidates)) in groups.into_iter().enumerate() {
+            if let Some((position, percent)) = self.position(tier) {
+                tiers[position].push(
+                    candidates
+                        .into_iter()
+                        .map(move |c| (c.weight as u64 * percent as u64, idx, c)),
+                );
+            }
+        }
+        tiers
+            .into_iter()
+            .flat_map(|groups| {
+                groups
+                    .into_iter()
+                    .kmerge_by(|a, b| a.0 > b.0 || (a.0 == b.0 && a.1 < b.1))
+            })
+            .map(|(_, _, candidate)| candidate)
+            .unique_by(|c| c.text.clone())
+    }
+
+    /// Sort `candidates` found by typing `input`, see [`Tier::of`].
+    pub fn rank(&self, input: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        let mut groups: Vec<(Tier, Vec<Candidate>)> = vec![];
+        for candidate in candidates {
+            let tier = Tier::of(input, &candidate);
+            match groups.iter_mut().find(|(kind, _)| *kind == tier) {
+                Some((_, group)) => group.push(candidate),
+                None => groups.push((tier, vec![candidate])),
+            }
+        }
+        self.arrange(groups).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(text: &str, code: &str, weight: u32) -> Candidate {
+        Candidate {
+            text: text.to_string(),
+            code: code.to_string(),
+            weight,
+            ..Default::default()
+        }
+    }
+
+    fn texts(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_rank() {
+        let candidates = vec![
+            candidate("戒", "aaak", 80),
+            candidate("式", "aa", 10),
+            candidate("工", "a", 50),
+            candidate("大", "aaa", 20),
+        ];
+
+        let ranked = RankingPolicy::default().rank("aa", candidates.clone());
+        assert_eq!(texts(&ranked), vec!["式", "戒", "大", "工"]);
+
+        let policy = RankingPolicy::from_config(&[
+            vec!["exact".to_string(), "completion:40".to_string()],
+            vec!["fuzzy".to_string()],
+        ])
+        .unwrap();
+        let ranked = policy.rank("aa", candidates.clone());
+        assert_eq!(texts(&ranked), vec!["戒", "式", "大", "工"]);
+
+        let policy = RankingPolicy::from_config(&[vec!["exact".to_string()]]).unwrap();
+        let ranked = policy.rank("aa", candidates);
+        assert_eq!(texts(&ranked), vec!["式"]);
+
+        assert!(RankingPolicy::from_config(&[vec!["exact:x".to_string()]]).is_err());
+        assert!(RankingPolicy::from_config(&[vec!["best".to_string()]]).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::{PoisonError, RwLock};
+
+use crate::dict::{Dictionary, ReverseIndex};
+
+use super::{candidates::Candidate, translator::Translator};
+
+/// Prefix starting a reverse lookup when the formula doesn't set one
+pub const DEFAULT_PREFIX: &str = "`";
+
+/// Finds words with the dictionary of another formula, usually pinyin, and
+/// tells their codes in the primary one.
+#[derive(Debug, Default)]
+pub struct ReverseLookup {
+    prefix: String,
+    dictionary: Dictionary,
+    /// Replaced when the primary dictionaries are reloaded
+    index: RwLock<ReverseIndex>,
+}
+
+impl ReverseLookup {
+    /// `index` is the reverse index of the primary dictionaries, see
+    /// [`crate::dict::build`].
+    pub fn new(prefix: impl Into<String>, dictionary: Dictionary, index: ReverseIndex) -> Self {
+        Self {
+            prefix: prefix.into(),
+            dictionary,
+            index: RwLock::new(index),
+        }
+    }
+
+    /// Swap in the reverse index of reloaded primary dictionaries.
+    pub fn replace_index(&self, index: ReverseIndex) {
+        *self.index.write().unwrap_or_else(PoisonError::into_inner) = index;
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Heaviest code of `text` in the primary dictionaries.
+    pub fn primary_code(&self, text: &str) -> Option<String> {
+        let index = self.index.read().unwrap_or_else(PoisonError::into_inner);
+        index.get(text)?.first().cloned()
+    }
+
+    /// Candidates of an input starting with the prefix, with their primary
+    /// codes as comment. `None` if the input isn't a reverse lookup.
+    pub fn lookup(&self, code: &str) -> Option<Vec<Candidate>> {
+        let code = code.strip_prefix(&self.prefix)?;
+        let index = self.index.read().unwrap_or_else(PoisonError::into_inner);
+        let candidates = self
+            .dictionary
+            .translate(code)
+            .into_iter()
+            .map(|candidate| {
+                let comment = index.get(&candidate.text).map(|codes| codes.join(" "));
+                Candidate {
+                    comment,
+                    ..candidate
+                }
+            })
+            .collect();
+        Some(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dict::DictItem;
+
+    #[test]
+    fn test_lookup() {
+        let mut pinyin = Dictionary::new();
+        pinyin.insert(
+            "ni",
+            vec![DictItem {
+                text: "你".to_string(),
+                code: "ni".to_string(),
+                weight: 10,
+                comment: None,
+            }],
+        );
+        let index =
+            ReverseIndex::from([("你".to_string(), vec!["wq".to_string(), "wqi".to_string()])]);
+        let lookup = ReverseLookup::new(DEFAULT_PREFIX, pinyin, index);
+
+        assert!(lookup.lookup("ni").is_none());
+        let candidates = lookup.lookup("`ni").unwrap();
+        assert_eq!(candidates[0].text, "你");
+        assert_eq!(candidates[0].comment.as_deref(), Some("wq wqi"));
+        assert_eq!(lookup.primary_code("你").as_deref(), Some("wq"));
+    }
+}
@@ -6,20 +6,26 @@ pub trait Segmentor {
 
 impl<V> Segmentor for StringPatriciaMap<V> {
     fn segment(&self, code: &str) -> Vec<String> {
-        let mut result = vec![];
-        let mut last = 0;
-        while last < code.len() {
-            let lcp = self.longest_common_prefix_len(&code[last..]);
-            if lcp > 0 {
-                result.push(code[last..last + lcp].to_string());
-                last += lcp;
-            } else {
-                result.push(code[last..].to_string());
-                last = code.len();
-            }
+        segment_by(code, |rest| self.longest_common_prefix_len(rest))
+    }
+}
+
+/// Split `code` greedily by the length of the longest known prefix of what
+/// is left of it.
+pub fn segment_by(code: &str, prefix_len: impl Fn(&str) -> usize) -> Vec<String> {
+    let mut result = vec![];
+    let mut last = 0;
+    while last < code.len() {
+        let lcp = prefix_len(&code[last..]);
+        if lcp > 0 {
+            result.push(code[last..last + lcp].to_string());
+            last += lcp;
+        } else {
+            result.push(code[last..].to_string());
+            last = code.len();
         }
-        result
     }
+    result
 }
 
 #[cfg(test)]
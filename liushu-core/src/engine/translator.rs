@@ -2,29 +2,68 @@ use itertools::Itertools;
 
 use crate::dict::Dictionary;
 
-use super::candidates::Candidate;
+use super::{candidates::Candidate, ranking::RankingPolicy};
 
 pub trait Translator {
-    fn translate(&self, code: &str) -> Vec<Candidate>;
+    /// Candidates of `code` ordered by `policy`.
+    fn translate_with(&self, code: &str, policy: &RankingPolicy) -> Vec<Candidate>;
+
+    /// Candidates of `code` with exact matches first, see [`RankingPolicy::default`].
+    fn translate(&self, code: &str) -> Vec<Candidate> {
+        self.translate_with(code, &RankingPolicy::default())
+    }
 }
 
 impl Translator for Dictionary {
-    fn translate(&self, code: &str) -> Vec<Candidate> {
+    fn translate_with(&self, code: &str, policy: &RankingPolicy) -> Vec<Candidate> {
         if code.is_empty() {
             return vec![];
         }
 
-        self.iter_prefix(code)
+        let candidates = self
+            .iter_prefix(code)
             .flat_map(|(_, value)| {
-                value.iter().map(|item| Candidate {
-                    text: item.text.clone(),
-                    code: item.code.clone(),
-                    comment: item.comment.clone(),
-                    weight: item.weight,
-                })
+                value
+                    .iter()
+                    .map(|item| Candidate::from(item).with_code_hint(code))
             })
-            .unique_by(|i| i.text.clone())
             .sorted_by_key(|i| std::cmp::Reverse(i.weight))
-            .collect()
+            .collect();
+        policy.rank(code, candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dict::DictItem;
+
+    #[test]
+    fn test_code_hint() {
+        let mut dict = Dictionary::new();
+        for (text, code, weight) in [("工", "a", 20), ("式", "aa", 10), ("戒", "aaak", 5)] {
+            dict.insert(
+                code,
+                vec![DictItem {
+                    text: text.to_string(),
+                    code: code.to_string(),
+                    weight,
+                    comment: None,
+                }],
+            );
+        }
+
+        let hints: Vec<_> = dict
+            .translate("aa")
+            .into_iter()
+            .map(|c| (c.text, c.code_hint))
+            .collect();
+        assert_eq!(
+            hints,
+            vec![
+                ("式".to_string(), None),
+                ("戒".to_string(), Some("ak".to_string()))
+            ]
+        );
     }
 }
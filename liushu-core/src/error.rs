@@ -1,61 +1,129 @@
+use std::error::Error as StdError;
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+use crate::corpus::CorpusReport;
+
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Errors of liushu. Messages leave out their sources, see
+/// [`LiushuError::full_message`].
 #[derive(Error, Debug)]
 pub enum LiushuError {
-    #[error("{0}")]
-    Other(String),
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("malformed dictionary {}{}", path.display(), line_suffix(*line))]
+    DictFormat {
+        path: PathBuf,
+        /// 1-based line number, when the problem can be located
+        line: Option<u64>,
+        #[source]
+        source: BoxError,
+    },
+    #[error(
+        "dictionary {} has format version {found}, expected {expected}, rebuild it with `liushu-dict build`",
+        path.display()
+    )]
+    DictVersion {
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+    },
+    #[error("malformed model")]
+    ModelFormat(#[source] redb::Error),
+    #[error("storage error")]
+    Storage(#[source] redb::Error),
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    #[error("too many malformed corpus lines (limit {max_errors}): {report}")]
+    MalformedCorpus {
+        report: CorpusReport,
+        max_errors: usize,
+    },
 }
 
-impl From<csv::Error> for LiushuError {
-    fn from(value: csv::Error) -> Self {
-        LiushuError::Other(format!("csv error: {}", value))
+impl LiushuError {
+    pub fn dict_format(
+        path: impl Into<PathBuf>,
+        line: Option<u64>,
+        source: impl Into<BoxError>,
+    ) -> Self {
+        LiushuError::DictFormat {
+            path: path.into(),
+            line,
+            source: source.into(),
+        }
+    }
+
+    /// Attach the dictionary path to a csv error, keeping the line it occurred on.
+    pub fn from_csv(path: impl Into<PathBuf>, err: csv::Error) -> Self {
+        let line = err.position().map(|pos| pos.line());
+        Self::dict_format(path, line, err)
+    }
+
+    /// The message followed by those of its sources, for showing to users.
+    pub fn full_message(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = self.source();
+        while let Some(err) = source {
+            message.push_str(": ");
+            message.push_str(&err.to_string());
+            source = err.source();
+        }
+        message
     }
 }
 
+fn line_suffix(line: Option<u64>) -> String {
+    line.map(|line| format!(" line {}", line))
+        .unwrap_or_default()
+}
+
 impl From<redb::Error> for LiushuError {
     fn from(value: redb::Error) -> Self {
-        LiushuError::Other(format!("redb error: {}", value))
+        match value {
+            redb::Error::Io(err) => LiushuError::Io(err),
+            redb::Error::Corrupted(_)
+            | redb::Error::UpgradeRequired(_)
+            | redb::Error::TableTypeMismatch { .. }
+            | redb::Error::TableIsMultimap(_)
+            | redb::Error::TableIsNotMultimap(_)
+            | redb::Error::TypeDefinitionChanged { .. }
+            | redb::Error::TableDoesNotExist(_) => LiushuError::ModelFormat(value),
+            _ => LiushuError::Storage(value),
+        }
     }
 }
 
 impl From<redb::StorageError> for LiushuError {
     fn from(value: redb::StorageError) -> Self {
-        LiushuError::Other(format!("redb error: {}", value))
+        redb::Error::from(value).into()
     }
 }
 
 impl From<redb::TableError> for LiushuError {
     fn from(value: redb::TableError) -> Self {
-        LiushuError::Other(format!("redb error: {}", value))
+        redb::Error::from(value).into()
     }
 }
 
 impl From<redb::TransactionError> for LiushuError {
     fn from(value: redb::TransactionError) -> Self {
-        LiushuError::Other(format!("redb error: {}", value))
+        redb::Error::from(value).into()
     }
 }
 
 impl From<redb::CommitError> for LiushuError {
     fn from(value: redb::CommitError) -> Self {
-        LiushuError::Other(format!("redb error: {}", value))
+        redb::Error::from(value).into()
     }
 }
 
 impl From<redb::DatabaseError> for LiushuError {
     fn from(value: redb::DatabaseError) -> Self {
-        LiushuError::Other(format!("redb error: {}", value))
-    }
-}
-
-impl From<bincode::Error> for LiushuError {
-    fn from(value: bincode::Error) -> Self {
-        LiushuError::Other(format!("bincode error: {}", value))
-    }
-}
-
-impl From<std::io::Error> for LiushuError {
-    fn from(value: std::io::Error) -> Self {
-        LiushuError::Other(format!("io error: {}", value))
+        redb::Error::from(value).into()
     }
 }
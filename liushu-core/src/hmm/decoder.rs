@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use itertools::Itertools;
+use redb::{Database, ReadOnlyMultimapTable, ReadOnlyTable, ReadableMultimapTable, ReadableTable};
+
+use crate::error::LiushuError;
+
+use super::{EMISS_TABLE, END, INIT_TABLE, MIN_F, STATES_TABLE, TRANS_TABLE};
+
+#[derive(Debug, Clone)]
+pub struct DecoderConfig {
+    /// Maximum number of syllables whose hanzi states are kept in memory, the
+    /// least recently used ones go first. 0 disables the cache
+    pub states_cache_capacity: usize,
+    /// Maximum number of transition probabilities kept in memory, 0 disables the cache
+    pub trans_cache_capacity: usize,
+    /// Prune unlikely states of every column, `None` searches exhaustively
+    pub beam: Option<Beam>,
+}
+
+impl Default for DecoderConfig {
+    fn default() -> Self {
+        Self {
+            states_cache_capacity: 4096,
+            trans_cache_capacity: 1 << 20,
+            beam: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Beam {
+    /// Number of states kept per column
+    pub width: usize,
+    /// States scoring more than this below the best state of their column are dropped
+    pub threshold: f64,
+}
+
+impl Default for Beam {
+    fn default() -> Self {
+        Self {
+            width: 32,
+            threshold: f64::INFINITY,
+        }
+    }
+}
+
+/// A hanzi that can emit some syllable, with its precomputed probabilities.
+#[derive(Debug)]
+struct State {
+    word: String,
+    log_init_prob: f64,
+    log_emiss_prob: f64,
+}
+
+/// States of the most recently used syllables, in a doubly linked list from
+/// the most to the least recently used one so that both lookups and
+/// evictions take constant time.
+#[derive(Debug, Default)]
+struct StatesCache {
+    /// Slot of each cached syllable in `nodes`
+    index: HashMap<String, usize>,
+    nodes: Vec<CacheNode>,
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+#[derive(Debug)]
+struct CacheNode {
+    py: String,
+    states: Arc<[State]>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl StatesCache {
+    fn get(&mut self, py: &str) -> Option<Arc<[State]>> {
+        let idx = *self.index.get(py)?;
+        self.unlink(idx);
+        self.push_front(idx);
+        Some(self.nodes[idx].states.clone())
+    }
+
+    /// Keep `states`, evicting the least recently used syllable when full.
+    fn insert(&mut self, py: &str, states: Arc<[State]>, capacity: usize) {
+        let idx = match self.index.get(py) {
+            // Another lookup of the same syllable got there first
+            Some(&idx) => {
+                self.nodes[idx].states = states;
+                self.unlink(idx);
+                idx
+            }
+            None if self.nodes.len() < capacity => {
+                self.nodes.push(CacheNode {
+                    py: py.to_string(),
+                    states,
+                    prev: None,
+                    next: None,
+                });
+                self.index.insert(py.to_string(), self.nodes.len() - 1);
+                self.nodes.len() - 1
+            }
+            None => {
+                let Some(idx) = self.tail else {
+                    return;
+                };
+                self.unlink(idx);
+                let node = &mut self.nodes[idx];
+                self.index.remove(&node.py);
+                node.py = py.to_string();
+                node.states = states;
+                self.index.insert(py.to_string(), idx);
+                idx
+            }
+        };
+        self.push_front(idx);
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let CacheNode { prev, next, .. } = self.nodes[idx];
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, idx: usize) {
+        self.nodes[idx].prev = None;
+        self.nodes[idx].next = self.head;
+        match self.head {
+            Some(head) => self.nodes[head].prev = Some(idx),
+            None => self.tail = Some(idx),
+        }
+        self.head = Some(idx);
+    }
+}
+
+#[derive(Debug, Default)]
+struct TransCache {
+    probs: HashMap<String, HashMap<String, f64>>,
+    len: usize,
+}
+
+/// A partial path ending at some state, pointing back to the `rank`-th best
+/// path of a state of the previous column.
+#[derive(Debug, Clone, Copy)]
+struct PathEntry {
+    score: f64,
+    prev: Option<(usize, usize)>,
+}
+
+struct Tables<'txn> {
+    init: ReadOnlyTable<'txn, &'static str, f64>,
+    trans: ReadOnlyTable<'txn, (&'static str, &'static str), f64>,
+    emiss: ReadOnlyTable<'txn, (&'static str, &'static str), f64>,
+    states: ReadOnlyMultimapTable<'txn, &'static str, &'static str>,
+}
+
+/// Viterbi decoder over a trained model, keeping the database open and
+/// caching the lookups shared by consecutive inputs.
+#[derive(Debug)]
+pub struct Decoder {
+    db: Database,
+    config: DecoderConfig,
+    states_cache: Mutex<StatesCache>,
+    trans_cache: Mutex<TransCache>,
+}
+
+impl Decoder {
+    pub fn new(db: Database) -> Self {
+        Self::with_config(db, DecoderConfig::default())
+    }
+
+    pub fn with_config(db: Database, config: DecoderConfig) -> Self {
+        Self {
+            db,
+            config,
+            states_cache: Default::default(),
+            trans_cache: Default::default(),
+        }
+    }
+
+    pub fn open(model_path: impl AsRef<Path>) -> Result<Self, LiushuError> {
+        Ok(Self::new(Database::open(model_path)?))
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Find the most probable hanzi sequence.
+    pub fn decode(&self, py_sequence: &[String]) -> Result<String, LiushuError> {
+        let sentences = self.decode_top_k(py_sequence, 1)?;
+        Ok(sentences.into_iter().next().unwrap_or_default())
+    }
+
+    /// Find the `top_k` most probable hanzi sequences, best first.
+    pub fn decode_top_k(
+        &self,
+        py_sequence: &[String],
+        top_k: usize,
+    ) -> Result<Vec<String>, LiushuError> {
+        if py_sequence.is_empty() || top_k == 0 {
+            return Ok(vec![]);
+        }
+
+        let read_txn = self.db.begin_read()?;
+        let tables = Tables {
+            init: read_txn.open_table(INIT_TABLE)?,
+            trans: read_txn.open_table(TRANS_TABLE)?,
+            emiss: read_txn.open_table(EMISS_TABLE)?,
+            states: read_txn.open_multimap_table(STATES_TABLE)?,
+        };
+        let columns = py_sequence
+            .iter()
+            .map(|py| self.states(&tables, py))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Every state keeps its `top_k` best partial paths, best first
+        let mut scores: Vec<Vec<Vec<PathEntry>>> = Vec::with_capacity(columns.len());
+
+        // Initialize the first score vector using the initial probabilities
+        let mut first: Vec<Vec<PathEntry>> = columns[0]
+            .iter()
+            .map(|state| {
+                vec![PathEntry {
+                    score: state.log_init_prob + state.log_emiss_prob,
+                    prev: None,
+                }]
+            })
+            .collect();
+        self.prune(&mut first);
+        scores.push(first);
+
+        // Iterate over the remaining columns, computing the best paths for each possible hanzi
+        for i in 1..columns.len() {
+            let mut current = Vec::with_capacity(columns[i].len());
+            for state in columns[i].iter() {
+                // Extend every kept path of every previous hanzi and choose the best ones
+                let mut entries = vec![];
+                for (prev_idx, prev_entries) in scores[i - 1].iter().enumerate() {
+                    if prev_entries.is_empty() {
+                        continue;
+                    }
+                    let prev_word = &columns[i - 1][prev_idx].word;
+                    let log_trans_prob = self.trans_prob(&tables, prev_word, &state.word)?;
+                    for (rank, prev_entry) in prev_entries.iter().enumerate() {
+                        entries.push(PathEntry {
+                            score: prev_entry.score + log_trans_prob + state.log_emiss_prob,
+                            prev: Some((prev_idx, rank)),
+                        });
+                    }
+                }
+                entries.sort_by(|a, b| b.score.total_cmp(&a.score));
+                entries.truncate(top_k);
+                current.push(entries);
+            }
+            self.prune(&mut current);
+            scores.push(current);
+        }
+
+        // Pick the best final paths and follow the back pointers
+        let last = columns.len() - 1;
+        let finals = scores[last]
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, entries)| {
+                entries
+                    .iter()
+                    .enumerate()
+                    .map(move |(rank, entry)| (entry.score, idx, rank))
+            })
+            .sorted_by(|a, b| b.0.total_cmp(&a.0))
+            .take(top_k);
+
+        let mut sentences = vec![];
+        for (_, idx, rank) in finals {
+            let mut words = vec![columns[last][idx].word.as_str()];
+            let mut cursor = (idx, rank);
+            for i in (1..=last).rev() {
+                match scores[i][cursor.0][cursor.1].prev {
+                    Some(prev) => {
+                        words.push(columns[i - 1][prev.0].word.as_str());
+                        cursor = prev;
+                    }
+                    None => break,
+                }
+            }
+            sentences.push(words.into_iter().rev().collect());
+        }
+
+        Ok(sentences)
+    }
+
+    /// The `limit` words most likely to follow `prev`, best first, with their
+    /// log transition probabilities.
+    pub fn predict(&self, prev: &str, limit: usize) -> Result<Vec<(String, f64)>, LiushuError> {
+        if prev.is_empty() || limit == 0 {
+            return Ok(vec![]);
+        }
+
+        let read_txn = self.db.begin_read()?;
+        let trans = read_txn.open_table(TRANS_TABLE)?;
+        let mut words = vec![];
+        for result in trans.range::<(&str, &str)>((prev, "")..)? {
+            let (key, value) = result?;
+            let (word, next) = key.value();
+            if word != prev {
+                break;
+            }
+            if next != END {
+                words.push((next.to_string(), value.value()));
+            }
+        }
+        words.sort_by(|a, b| b.1.total_cmp(&a.1));
+        words.truncate(limit);
+        Ok(words)
+    }
+
+    /// Drop the paths of the states falling out of the beam. Pruned states keep
+    /// their slot so that back pointers stay valid.
+    fn prune(&self, column: &mut [Vec<PathEntry>]) {
+        let Some(beam) = &self.config.beam else {
+            return;
+        };
+
+        let ranked = column
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entries)| entries.first().map(|entry| (idx, entry.score)))
+            .sorted_by(|a, b| b.1.total_cmp(&a.1))
+            .collect::<Vec<_>>();
+        let Some(&(_, best)) = ranked.first() else {
+            return;
+        };
+
+        for (pos, (idx, score)) in ranked.into_iter().enumerate() {
+            if pos >= beam.width || score < best - beam.threshold {
+                column[idx].clear();
+            }
+        }
+    }
+
+    fn states(&self, tables: &Tables, py: &str) -> Result<Arc<[State]>, LiushuError> {
+        let capacity = self.config.states_cache_capacity;
+        if capacity > 0 {
+            let mut cache = self
+                .states_cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(states) = cache.get(py) {
+                return Ok(states);
+            }
+        }
+
+        let mut states = vec![];
+        for result in tables.states.get(py)? {
+            let guard = result?;
+            let word = guard.value();
+            let log_init_prob = tables.init.get(word)?.map(|x| x.value()).unwrap_or(MIN_F);
+            let log_emiss_prob = tables
+                .emiss
+                .get(&(word, py))?
+                .map(|x| x.value())
+                .unwrap_or(MIN_F);
+            states.push(State {
+                word: word.to_string(),
+                log_init_prob,
+                log_emiss_prob,
+            });
+        }
+        let states: Arc<[State]> = states.into();
+
+        if capacity > 0 {
+            self.states_cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(py, states.clone(), capacity);
+        }
+
+        Ok(states)
+    }
+
+    /// Transition probability from `prev` to `cur`, only holding the cache
+    /// lock while it is looked up or filled so that concurrent decodes don't
+    /// wait for each other.
+    fn trans_prob(&self, tables: &Tables, prev: &str, cur: &str) -> Result<f64, LiushuError> {
+        let capacity = self.config.trans_cache_capacity;
+        if capacity > 0 {
+            let cache = self
+                .trans_cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(&prob) = cache.probs.get(prev).and_then(|m| m.get(cur)) {
+                return Ok(prob);
+            }
+        }
+
+        let prob = tables
+            .trans
+            .get(&(prev, cur))?
+            .map(|x| x.value())
+            .unwrap_or(MIN_F);
+
+        if capacity > 0 {
+            let mut cache = self
+                .trans_cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Start over instead of tracking recency, hot entries come back quickly
+            if cache.len >= capacity {
+                cache.probs.clear();
+                cache.len = 0;
+            }
+            let inserted = cache
+                .probs
+                .entry(prev.to_string())
+                .or_default()
+                .insert(cur.to_string(), prob)
+                .is_none();
+            cache.len += usize::from(inserted);
+        }
+
+        Ok(prob)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::hmm::train_to_db;
+    use crate::test_util::TempDir;
+
+    const SYLLABLES: usize = 3;
+    const STATES_PER_SYLLABLE: usize = 4;
+
+    fn hanzi(syllable: usize, state: usize) -> char {
+        char::from_u32(0x4e00 + (syllable * STATES_PER_SYLLABLE + state) as u32).unwrap()
+    }
+
+    /// Train a model on a small corpus made up like the one of the decoder
+    /// benchmark.
+    fn synthetic_model(dir: &Path) -> Database {
+        let mut seed = 42u64;
+        let mut next = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 33) % bound as u64) as usize
+        };
+        let mut corpus = String::new();
+        for _ in 0..200 {
+            let (words, pinyins): (Vec<String>, Vec<String>) = (0..5)
+                .map(|_| {
+                    let syl = next(SYLLABLES);
+                    let bound = next(STATES_PER_SYLLABLE) + 1;
+                    (hanzi(syl, next(bound)).to_string(), format!("py{}", syl))
+                })
+                .unzip();
+            corpus += &format!("{}\t{}\n", words.join(" "), pinyins.join(" "));
+        }
+        let corpus_path = dir.join("corpus.txt");
+        fs::write(&corpus_path, corpus).unwrap();
+        let db = Database::create(dir.join("model.redb")).unwrap();
+        train_to_db(&corpus_path, &db, 0).unwrap();
+        db
+    }
+
+    /// Scores of every sentence of `py_sequence`, best first.
+    fn exhaustive(decoder: &Decoder, py_sequence: &[String]) -> Vec<(f64, String)> {
+        let read_txn = decoder.db.begin_read().unwrap();
+        let tables = Tables {
+            init: read_txn.open_table(INIT_TABLE).unwrap(),
+            trans: read_txn.open_table(TRANS_TABLE).unwrap(),
+            emiss: read_txn.open_table(EMISS_TABLE).unwrap(),
+            states: read_txn.open_multimap_table(STATES_TABLE).unwrap(),
+        };
+        let columns: Vec<_> = py_sequence
+            .iter()
+            .map(|py| decoder.states(&tables, py).unwrap())
+            .collect();
+
+        let mut paths: Vec<(f64, Vec<&State>)> = columns[0]
+            .iter()
+            .map(|state| (state.log_init_prob + state.log_emiss_prob, vec![state]))
+            .collect();
+        for column in &columns[1..] {
+            let mut next = vec![];
+            for (score, path) in &paths {
+                let prev = &path[path.len() - 1].word;
+                for state in column.iter() {
+                    let trans = decoder.trans_prob(&tables, prev, &state.word).unwrap();
+                    let mut path = path.clone();
+                    path.push(state);
+                    next.push((score + trans + state.log_emiss_prob, path));
+                }
+            }
+            paths = next;
+        }
+        paths
+            .into_iter()
+            .map(|(score, path)| (score, path.iter().map(|s| s.word.as_str()).collect()))
+            .sorted_by(|a: &(f64, String), b| b.0.total_cmp(&a.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        let dir = TempDir::new("decoder");
+        let decoder = Decoder::with_config(
+            synthetic_model(&dir),
+            DecoderConfig {
+                states_cache_capacity: 2,
+                ..Default::default()
+            },
+        );
+
+        let input: Vec<String> = [0, 1, 2, 1]
+            .iter()
+            .map(|syl| format!("py{}", syl))
+            .collect();
+        let expected = exhaustive(&decoder, &input);
+        assert_eq!(decoder.decode(&input).unwrap(), expected[0].1);
+        assert_eq!(decoder.decode(&[]).unwrap(), "");
+
+        let sentences = decoder.decode_top_k(&input, 5).unwrap();
+        assert_eq!(sentences.len(), 5);
+        let scores: HashMap<&str, f64> = expected
+            .iter()
+            .map(|(score, text)| (text.as_str(), *score))
+            .collect();
+        for (sentence, (score, _)) in sentences.iter().zip(&expected) {
+            assert!((scores[sentence.as_str()] - score).abs() < 1e-9);
+        }
+        assert!(sentences.iter().all_unique());
+        assert!(decoder.decode_top_k(&input, 0).unwrap().is_empty());
+
+        // the cache keeps the most recently used syllables
+        let cache = decoder.states_cache.lock().unwrap();
+        assert_eq!(cache.index.len(), 2);
+        assert!(cache.index.contains_key("py1") && cache.index.contains_key("py2"));
+    }
+
+    #[test]
+    fn test_states_cache() {
+        let mut cache = StatesCache::default();
+        let states = || -> Arc<[State]> { Arc::new([]) };
+        cache.insert("a", states(), 2);
+        cache.insert("b", states(), 2);
+        assert!(cache.get("a").is_some());
+        // b is the least recently used one
+        cache.insert("c", states(), 2);
+        assert!(cache.get("b").is_none());
+        cache.insert("a", states(), 2);
+        cache.insert("d", states(), 2);
+        assert!(cache.get("c").is_none());
+        assert!(cache.get("a").is_some() && cache.get("d").is_some());
+        assert_eq!(cache.nodes.len(), 2);
+    }
+
+    #[test]
+    fn test_beam() {
+        let dir = TempDir::new("beam");
+        synthetic_model(&dir);
+        let input: Vec<String> = [2, 0, 1, 1, 0]
+            .iter()
+            .map(|syl| format!("py{}", syl))
+            .collect();
+
+        let decode = |beam| {
+            let config = DecoderConfig {
+                beam,
+                ..Default::default()
+            };
+            let db = Database::open(dir.join("model.redb")).unwrap();
+            Decoder::with_config(db, config)
+                .decode_top_k(&input, 8)
+                .unwrap()
+        };
+
+        // a beam as wide as the states of a syllable prunes nothing
+        let wide = Beam {
+            width: STATES_PER_SYLLABLE,
+            ..Default::default()
+        };
+        assert_eq!(decode(Some(wide)), decode(None));
+        let narrow = Beam {
+            width: 1,
+            ..Default::default()
+        };
+        assert_eq!(decode(Some(narrow)).len(), 1);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::corpus::{check_corpus, parse_corpus_line};
+use crate::error::LiushuError;
+
+use super::decoder::Decoder;
+
+/// Accuracy of a model measured on a held-out corpus.
+#[derive(Debug, Default, Serialize)]
+pub struct EvalReport {
+    pub top_k: usize,
+    pub sentences: usize,
+    pub sentence_hits: usize,
+    pub top_k_hits: usize,
+    pub chars: usize,
+    pub char_errors: usize,
+    pub sentence_accuracy: f64,
+    pub char_accuracy: f64,
+    pub top_k_hit_rate: f64,
+    /// Malformed lines that were skipped
+    pub skipped_lines: Vec<usize>,
+    /// Lines whose best conversion differs from the expected text
+    pub diffs: Vec<LineDiff>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LineDiff {
+    pub line: usize,
+    pub pinyin: String,
+    pub expected: String,
+    pub actual: String,
+    pub char_errors: usize,
+    /// Rank of the expected text among the `top_k` conversions, if present
+    pub rank: Option<usize>,
+}
+
+/// Convert every pinyin line of an annotated corpus and compare the results
+/// with its text, skipping malformed lines as long as there are at most
+/// `max_errors` of them.
+pub fn evaluate(
+    corpus_file: impl AsRef<Path>,
+    decoder: &Decoder,
+    top_k: usize,
+    max_errors: usize,
+) -> Result<EvalReport, LiushuError> {
+    let corpus = check_corpus(corpus_file.as_ref(), max_errors)?;
+    let mut report = EvalReport {
+        top_k,
+        skipped_lines: corpus.errors.iter().map(|err| err.line).collect(),
+        ..Default::default()
+    };
+
+    let reader = BufReader::new(File::open(corpus_file)?);
+    for (idx, line) in reader.lines().enumerate() {
+        let Ok(Some(line)) = parse_corpus_line(&line?) else {
+            continue;
+        };
+        let expected: String = line.words.concat();
+        let py_sequence = line.pinyins;
+
+        let candidates = decoder.decode_top_k(&py_sequence, top_k.max(1))?;
+        let actual = candidates.first().cloned().unwrap_or_default();
+        let rank = candidates.iter().take(top_k).position(|c| *c == expected);
+        let char_errors = edit_distance(&expected, &actual);
+
+        report.sentences += 1;
+        report.chars += expected.chars().count();
+        report.char_errors += char_errors;
+        if rank.is_some() {
+            report.top_k_hits += 1;
+        }
+        if actual == expected {
+            report.sentence_hits += 1;
+        } else {
+            report.diffs.push(LineDiff {
+                line: idx + 1,
+                pinyin: py_sequence.join(" "),
+                expected,
+                actual,
+                char_errors,
+                rank,
+            });
+        }
+    }
+
+    if report.sentences > 0 {
+        let sentences = report.sentences as f64;
+        report.sentence_accuracy = report.sentence_hits as f64 / sentences;
+        report.top_k_hit_rate = report.top_k_hits as f64 / sentences;
+    }
+    if report.chars > 0 {
+        let errors = report.char_errors.min(report.chars);
+        report.char_accuracy = 1.0 - errors as f64 / report.chars as f64;
+    }
+
+    Ok(report)
+}
+
+/// Levenshtein distance counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use redb::Database;
+
+    use super::*;
+    use crate::hmm::train_to_db;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_evaluate() {
+        let dir = TempDir::new("eval");
+        let train = dir.join("train.txt");
+        fs::write(&train, "你 好\tni hao\n你 好\tni hao\n泥 巴\tni ba\n").unwrap();
+        let db = Database::create(dir.join("model.redb")).unwrap();
+        train_to_db(&train, &db, 0).unwrap();
+        let decoder = Decoder::new(db);
+
+        let corpus = dir.join("eval.txt");
+        fs::write(&corpus, "你 好\tni hao\n泥 好\tni hao\nmalformed\n").unwrap();
+        let err = evaluate(&corpus, &decoder, 2, 0).unwrap_err();
+        assert!(matches!(err, LiushuError::MalformedCorpus { .. }));
+
+        let report = evaluate(&corpus, &decoder, 2, 1).unwrap();
+        assert_eq!(report.skipped_lines, vec![3]);
+        assert_eq!(report.sentences, 2);
+        assert_eq!(report.sentence_hits, 1);
+        assert_eq!(report.sentence_accuracy, 0.5);
+        assert_eq!((report.char_errors, report.chars), (1, 4));
+        assert_eq!(report.char_accuracy, 0.75);
+        assert_eq!(report.diffs.len(), 1);
+        let diff = &report.diffs[0];
+        assert_eq!(diff.line, 2);
+        assert_eq!(diff.pinyin, "ni hao");
+        assert_eq!(
+            (diff.expected.as_str(), diff.actual.as_str()),
+            ("泥好", "你好")
+        );
+        assert_eq!(diff.rank, Some(1));
+        assert_eq!(report.top_k_hits, 2);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("你好世界", "你好世界"), 0);
+        assert_eq!(edit_distance("你好世界", "拟好是界"), 2);
+        assert_eq!(edit_distance("你好", "你好吗"), 1);
+        assert_eq!(edit_distance("", "世界"), 2);
+    }
+}
@@ -1,4 +1,12 @@
+pub mod config;
+pub mod corpus;
 pub mod dict;
 pub mod engine;
 pub mod error;
 pub mod hmm;
+pub mod punctuation;
+pub mod session;
+pub mod user_dict;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
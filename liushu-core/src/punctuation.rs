@@ -0,0 +1,104 @@
+/// Maps ASCII punctuation to its full-width Chinese form.
+///
+/// Quotes alternate between the opening and the closing form, so the
+/// converter keeps track of which one comes next.
+#[derive(Debug, Default, Clone)]
+pub struct Punctuator {
+    double_quote_open: bool,
+    single_quote_open: bool,
+}
+
+impl Punctuator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The full-width form of `ch`, or `None` if it isn't a mapped punctuation.
+    pub fn convert(&mut self, ch: char) -> Option<&'static str> {
+        let punct = match ch {
+            ',' => "，",
+            '.' => "。",
+            '?' => "？",
+            '!' => "！",
+            ';' => "；",
+            ':' => "：",
+            '\\' => "、",
+            '[' => "「",
+            ']' => "」",
+            '{' => "『",
+            '}' => "』",
+            '(' => "（",
+            ')' => "）",
+            '<' => "《",
+            '>' => "》",
+            '~' => "～",
+            '$' => "￥",
+            '^' => "……",
+            '_' => "——",
+            '"' => {
+                self.double_quote_open = !self.double_quote_open;
+                if self.double_quote_open {
+                    "“"
+                } else {
+                    "”"
+                }
+            }
+            '\'' => {
+                self.single_quote_open = !self.single_quote_open;
+                if self.single_quote_open {
+                    "‘"
+                } else {
+                    "’"
+                }
+            }
+            _ => return None,
+        };
+        Some(punct)
+    }
+
+    /// Forget unclosed quotes, e.g. when the input context changes.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The full-width form of a printable ASCII char, e.g. `Ａ` for `A`.
+pub fn to_full_width(ch: char) -> Option<char> {
+    match ch {
+        ' ' => Some('\u{3000}'),
+        '!'..='~' => char::from_u32(ch as u32 + 0xfee0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let mut punctuator = Punctuator::new();
+
+        assert_eq!(punctuator.convert(','), Some("，"));
+        assert_eq!(punctuator.convert('\\'), Some("、"));
+        assert_eq!(punctuator.convert('a'), None);
+
+        assert_eq!(punctuator.convert('"'), Some("“"));
+        assert_eq!(punctuator.convert('\''), Some("‘"));
+        assert_eq!(punctuator.convert('\''), Some("’"));
+        assert_eq!(punctuator.convert('"'), Some("”"));
+
+        punctuator.convert('"');
+        punctuator.reset();
+        assert_eq!(punctuator.convert('"'), Some("“"));
+    }
+
+    #[test]
+    fn test_to_full_width() {
+        assert_eq!(to_full_width('A'), Some('Ａ'));
+        assert_eq!(to_full_width('1'), Some('１'));
+        assert_eq!(to_full_width('~'), Some('～'));
+        assert_eq!(to_full_width(' '), Some('\u{3000}'));
+        assert_eq!(to_full_width('你'), None);
+    }
+}
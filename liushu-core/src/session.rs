@@ -0,0 +1,1036 @@
+use std::sync::Arc;
+
+use crate::engine::{candidates::Candidate, ranking::Tier, segmentor::Segmentor, Engine};
+use crate::error::LiushuError;
+use crate::punctuation::{to_full_width, Punctuator};
+
+const DEFAULT_PAGE_SIZE: usize = 5;
+
+/// Keys a session understands, independent of how the frontend receives them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A printable character, already translated by the keyboard layout
+    Char(char),
+    Space,
+    Enter,
+    Backspace,
+    Escape,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+    /// Toggles between Chinese and ASCII mode
+    Shift,
+    /// Toggles between full-width and half-width punctuation in Chinese mode
+    TogglePunctuation,
+    /// Toggles between full-width and half-width ASCII chars
+    ToggleFullWidth,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyResult {
+    /// Whether the key was consumed, unhandled keys should be forwarded to the application
+    pub handled: bool,
+    /// Text to commit to the application
+    pub commit: Option<String>,
+}
+
+impl KeyResult {
+    fn handled() -> Self {
+        Self {
+            handled: true,
+            commit: None,
+        }
+    }
+
+    fn unhandled() -> Self {
+        Self::default()
+    }
+
+    fn commit(text: String) -> Self {
+        Self {
+            handled: true,
+            commit: Some(text),
+        }
+    }
+}
+
+/// Everything a frontend needs to render the composition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionState {
+    pub preedit: String,
+    /// Length of the already converted head of the preedit, in chars
+    pub confirmed: usize,
+    /// Caret position in the preedit, in chars
+    pub cursor: usize,
+    /// Candidates of the current page
+    pub candidates: Vec<Candidate>,
+    pub page: usize,
+    pub has_prev_page: bool,
+    pub has_next_page: bool,
+    pub ascii_mode: bool,
+    pub full_width_punct: bool,
+    /// Whether letters, digits and symbols are committed in full-width forms
+    pub full_width: bool,
+}
+
+/// The state of a fresh session.
+impl Default for SessionState {
+    fn default() -> Self {
+        Self {
+            preedit: String::new(),
+            confirmed: 0,
+            cursor: 0,
+            candidates: vec![],
+            page: 0,
+            has_prev_page: false,
+            has_next_page: false,
+            ascii_mode: false,
+            full_width_punct: true,
+            full_width: false,
+        }
+    }
+}
+
+/// Composition state of one input context, shared by all frontends.
+#[derive(Debug)]
+pub struct Session {
+    engine: Arc<Engine>,
+    page_size: usize,
+    /// Leading segments converted but not committed yet, as `(code, text)`
+    confirmed: Vec<(String, String)>,
+    input: String,
+    /// Caret position in `input`, in bytes
+    cursor: usize,
+    candidates: Vec<Candidate>,
+    /// How many dictionary candidates are ranked, grows when paging
+    limit: usize,
+    /// Whether the dictionary has no candidates beyond `limit`
+    exhausted: bool,
+    /// Sentence and partial candidates of the input, kept while paging
+    extra: Vec<(Tier, Vec<Candidate>)>,
+    /// Text of the last commit, predictions follow it until other input
+    last_commit: Option<String>,
+    page: usize,
+    ascii_mode: bool,
+    full_width_punct: bool,
+    full_width: bool,
+    punctuator: Punctuator,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(Arc::default())
+    }
+}
+
+impl Session {
+    pub fn new(engine: Arc<Engine>) -> Self {
+        Self {
+            engine,
+            page_size: DEFAULT_PAGE_SIZE,
+            confirmed: vec![],
+            input: String::new(),
+            cursor: 0,
+            candidates: vec![],
+            limit: 0,
+            exhausted: true,
+            extra: vec![],
+            last_commit: None,
+            page: 0,
+            ascii_mode: false,
+            full_width_punct: true,
+            full_width: false,
+            punctuator: Punctuator::new(),
+        }
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    pub fn engine(&self) -> &Arc<Engine> {
+        &self.engine
+    }
+
+    pub fn is_composing(&self) -> bool {
+        !self.input.is_empty() || !self.confirmed.is_empty()
+    }
+
+    /// Whether the candidates are next words predicted after a commit, shown
+    /// without a preedit.
+    pub fn is_predicting(&self) -> bool {
+        !self.is_composing() && !self.candidates.is_empty()
+    }
+
+    pub fn process_key(&mut self, key: Key) -> Result<KeyResult, LiushuError> {
+        if key == Key::Shift {
+            let commit = self.toggle_ascii_mode();
+            return Ok(KeyResult {
+                handled: true,
+                commit,
+            });
+        }
+        if key == Key::TogglePunctuation {
+            self.full_width_punct = !self.full_width_punct;
+            return Ok(KeyResult::handled());
+        }
+        if key == Key::ToggleFullWidth {
+            self.full_width = !self.full_width;
+            return Ok(KeyResult::handled());
+        }
+        if self.ascii_mode {
+            return Ok(self.full_width_key(key));
+        }
+        if !self.is_composing() {
+            // Predictions are picked with digits, anything but typing the
+            // next word moves away from the last commit
+            match key {
+                Key::Char('1'..='9') | Key::PageUp | Key::PageDown if self.is_predicting() => {}
+                Key::Char('a'..='z') => self.reset(),
+                Key::Escape if self.is_predicting() => {
+                    self.clear();
+                    return Ok(KeyResult::handled());
+                }
+                _ => self.clear(),
+            }
+        }
+
+        match key {
+            Key::Char(ch @ 'a'..='z') => {
+                self.input(ch.encode_utf8(&mut [0; 4]))?;
+                Ok(KeyResult::handled())
+            }
+            Key::Char(ch @ '1'..='9') if self.is_composing() || self.is_predicting() => {
+                let index = ch as usize - '1' as usize;
+                Ok(self
+                    .select(index)?
+                    .map(KeyResult::commit)
+                    .unwrap_or_else(KeyResult::handled))
+            }
+            // Opens the symbol tables, e.g. `/jt` for arrows, or reverse lookup
+            Key::Char(ch) if !self.is_composing() && self.engine.starts_input(ch) => {
+                self.input(ch.encode_utf8(&mut [0; 4]))?;
+                Ok(KeyResult::handled())
+            }
+            Key::Char(ch) if ch.is_ascii_punctuation() => self.punctuate(ch),
+            Key::Space if self.is_composing() => Ok(self
+                .select(0)?
+                .map(KeyResult::commit)
+                .unwrap_or_else(KeyResult::handled)),
+            Key::Enter if self.is_composing() => {
+                let raw = self.confirmed_text() + &self.input;
+                self.clear();
+                Ok(KeyResult::commit(raw))
+            }
+            Key::Backspace if self.is_composing() => {
+                self.backspace()?;
+                Ok(KeyResult::handled())
+            }
+            Key::Delete if self.is_composing() => {
+                self.delete()?;
+                Ok(KeyResult::handled())
+            }
+            Key::Left if self.is_composing() => {
+                let cursor = self.prev_boundary();
+                self.move_cursor(cursor)?;
+                Ok(KeyResult::handled())
+            }
+            Key::Right if self.is_composing() => {
+                let cursor = self.next_boundary();
+                self.move_cursor(cursor)?;
+                Ok(KeyResult::handled())
+            }
+            Key::Home if self.is_composing() => {
+                self.move_cursor(0)?;
+                Ok(KeyResult::handled())
+            }
+            Key::End if self.is_composing() => {
+                self.move_cursor(self.input.len())?;
+                Ok(KeyResult::handled())
+            }
+            Key::Escape if self.is_composing() => {
+                self.clear();
+                Ok(KeyResult::handled())
+            }
+            Key::PageDown if self.is_composing() || self.is_predicting() => {
+                self.next_page()?;
+                Ok(KeyResult::handled())
+            }
+            Key::PageUp if self.is_composing() || self.is_predicting() => {
+                self.prev_page();
+                Ok(KeyResult::handled())
+            }
+            _ => Ok(self.full_width_key(key)),
+        }
+    }
+
+    /// Insert text at the caret, as if typed.
+    pub fn input(&mut self, text: &str) -> Result<(), LiushuError> {
+        self.input.insert_str(self.cursor, text);
+        self.cursor += text.len();
+        self.refresh()
+    }
+
+    /// Remove the char before the caret. At the start of the input, the last
+    /// confirmed segment is turned back into its code instead.
+    pub fn backspace(&mut self) -> Result<(), LiushuError> {
+        if self.cursor == 0 {
+            if let Some((code, _)) = self.confirmed.pop() {
+                self.input.insert_str(0, &code);
+                self.cursor = code.len();
+            }
+        } else {
+            let prev = self.prev_boundary();
+            self.input.replace_range(prev..self.cursor, "");
+            self.cursor = prev;
+        }
+        self.refresh()
+    }
+
+    /// Remove the char after the caret.
+    pub fn delete(&mut self) -> Result<(), LiushuError> {
+        if self.cursor < self.input.len() {
+            let next = self.next_boundary();
+            self.input.replace_range(self.cursor..next, "");
+        }
+        self.refresh()
+    }
+
+    /// Move the caret to a byte offset of the input, looking up candidates for
+    /// the new position.
+    pub fn move_cursor(&mut self, cursor: usize) -> Result<(), LiushuError> {
+        let cursor = cursor.min(self.input.len());
+        if !self.input.is_char_boundary(cursor) {
+            return Err(LiushuError::InvalidInput(format!(
+                "cursor {} is not at a char boundary",
+                cursor
+            )));
+        }
+        self.cursor = cursor;
+        self.refresh()
+    }
+
+    /// Select the `index`-th candidate of the current page.
+    ///
+    /// A candidate covering only the head of the input is confirmed and the
+    /// rest keeps converting. Once nothing is left, the confirmed text is
+    /// returned for commit and the words likely to follow it are predicted.
+    pub fn select(&mut self, index: usize) -> Result<Option<String>, LiushuError> {
+        if index >= self.page_size {
+            return Ok(None);
+        }
+        let Some(candidate) = self.candidates.get(self.page * self.page_size + index) else {
+            return Ok(None);
+        };
+
+        let end = self.lookup_end();
+        // Candidates of the leading segments have a code shorter than the span,
+        // completions a longer one
+        let end = if !candidate.code.is_empty()
+            && candidate.code.len() < end
+            && self.input.starts_with(&candidate.code)
+        {
+            candidate.code.len()
+        } else {
+            end
+        };
+        let code = self.input[..end].to_string();
+        self.confirmed.push((code, candidate.text.clone()));
+        self.input.replace_range(..end, "");
+        self.cursor = self.input.len();
+
+        if !self.input.is_empty() {
+            self.refresh()?;
+            return Ok(None);
+        }
+
+        let confirmed = std::mem::take(&mut self.confirmed);
+        for (code, text) in &confirmed {
+            self.engine.learn(code, text)?;
+        }
+        let text = if confirmed.len() > 1 {
+            let (code, text): (String, String) = confirmed.into_iter().unzip();
+            // Shape-code phrases have their own codes, not the typed ones
+            let code = self.engine.encode(&text).unwrap_or(code);
+            self.engine.learn(&code, &text)?;
+            text
+        } else {
+            confirmed
+                .into_iter()
+                .next()
+                .map(|(_, text)| text)
+                .unwrap_or_default()
+        };
+        if let Some(prev) = self.last_commit.take() {
+            self.engine.learn_next(&prev, &text)?;
+        }
+        self.clear();
+        self.candidates = self.engine.predict(&text, 2 * self.page_size)?;
+        self.last_commit = Some(text.clone());
+        Ok(Some(text))
+    }
+
+    pub fn next_page(&mut self) -> Result<(), LiushuError> {
+        // Keep a page ahead so that `has_next_page` is right
+        if (self.page + 3) * self.page_size > self.candidates.len() && !self.exhausted {
+            self.limit = (self.page + 3) * self.page_size;
+            self.load()?;
+        }
+        if (self.page + 1) * self.page_size < self.candidates.len() {
+            self.page += 1;
+        }
+        Ok(())
+    }
+
+    pub fn prev_page(&mut self) {
+        self.page = self.page.saturating_sub(1);
+    }
+
+    pub fn clear(&mut self) {
+        self.confirmed.clear();
+        self.input.clear();
+        self.cursor = 0;
+        self.last_commit = None;
+        self.reset();
+    }
+
+    /// Switch between Chinese and ASCII mode, committing the preedit if composing.
+    pub fn toggle_ascii_mode(&mut self) -> Option<String> {
+        self.ascii_mode = !self.ascii_mode;
+        let raw = self
+            .is_composing()
+            .then(|| self.confirmed_text() + &self.input);
+        self.clear();
+        raw
+    }
+
+    /// Forget unclosed quotes, e.g. when the input context changes.
+    pub fn reset_punctuation(&mut self) {
+        self.punctuator.reset();
+    }
+
+    pub fn state(&self) -> SessionState {
+        let start = self.page * self.page_size;
+        let confirmed = self.confirmed_text();
+        let confirmed_len = confirmed.chars().count();
+        SessionState {
+            preedit: confirmed + &self.input,
+            confirmed: confirmed_len,
+            cursor: confirmed_len + self.input[..self.cursor].chars().count(),
+            candidates: self
+                .candidates
+                .iter()
+                .skip(start)
+                .take(self.page_size)
+                .cloned()
+                .collect(),
+            page: self.page,
+            has_prev_page: self.page > 0,
+            has_next_page: start + self.page_size < self.candidates.len(),
+            ascii_mode: self.ascii_mode,
+            full_width_punct: self.full_width_punct,
+            full_width: self.full_width,
+        }
+    }
+
+    /// Commit the pending composition with the top candidates, followed by
+    /// the punctuation.
+    fn punctuate(&mut self, ch: char) -> Result<KeyResult, LiushuError> {
+        let punct = if self.full_width_punct {
+            self.punctuator.convert(ch)
+        } else {
+            None
+        };
+        if punct.is_none() && !self.is_composing() {
+            return Ok(self.full_width_key(Key::Char(ch)));
+        }
+
+        let mut text = self.commit_top()?;
+        self.clear();
+        match punct {
+            Some(punct) => text.push_str(punct),
+            None => text.push(ch),
+        }
+        Ok(KeyResult::commit(text))
+    }
+
+    /// Commit the full-width form of a char key outside of composition, in
+    /// full-width mode.
+    fn full_width_key(&self, key: Key) -> KeyResult {
+        let ch = match key {
+            Key::Char(ch) => ch,
+            Key::Space => ' ',
+            _ => return KeyResult::unhandled(),
+        };
+        match to_full_width(ch) {
+            Some(ch) if self.full_width && !self.is_composing() => {
+                KeyResult::commit(ch.to_string())
+            }
+            _ => KeyResult::unhandled(),
+        }
+    }
+
+    /// Convert what is left of the input with the top candidates, returning
+    /// the whole preedit. Input without candidates is kept as is.
+    fn commit_top(&mut self) -> Result<String, LiushuError> {
+        while !self.input.is_empty() && !self.candidates.is_empty() {
+            self.page = 0;
+            if let Some(text) = self.select(0)? {
+                return Ok(text);
+            }
+        }
+
+        let text = self.confirmed_text() + &self.input;
+        self.clear();
+        Ok(text)
+    }
+
+    fn confirmed_text(&self) -> String {
+        self.confirmed
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.candidates.clear();
+        self.extra.clear();
+        self.page = 0;
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.input[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.input[self.cursor..]
+            .chars()
+            .next()
+            .map(|ch| self.cursor + ch.len_utf8())
+            .unwrap_or(self.cursor)
+    }
+
+    /// End of the input looked up for candidates: the end of the segment
+    /// around the caret, or the whole input when the caret is at its end.
+    fn lookup_end(&self) -> usize {
+        if self.cursor >= self.input.len() {
+            return self.input.len();
+        }
+
+        let mut end = 0;
+        for segment in self.engine.segment(&self.input) {
+            end += segment.len();
+            if end >= self.cursor.max(1) {
+                break;
+            }
+        }
+        end.min(self.input.len())
+    }
+
+    fn refresh(&mut self) -> Result<(), LiushuError> {
+        self.reset();
+        self.limit = 2 * self.page_size;
+        self.load_extra()?;
+        self.load()
+    }
+
+    /// Look up the candidates of the input, ranking up to `limit` of the
+    /// dictionary's. Paging only calls this again, [`Self::load_extra`] is
+    /// done once per input.
+    fn load(&mut self) -> Result<(), LiushuError> {
+        self.candidates.clear();
+        self.exhausted = true;
+        if self.input.is_empty() {
+            return Ok(());
+        }
+
+        let code = &self.input[..self.lookup_end()];
+        let mut exact = vec![];
+        let mut completions = vec![];
+        let (candidates, more) = self.engine.search_top_with_more(code, self.limit)?;
+        for candidate in candidates {
+            match Tier::of(code, &candidate) {
+                Tier::Completion => completions.push(candidate),
+                _ => exact.push(candidate),
+            }
+        }
+        self.exhausted = !more;
+
+        let mut groups = vec![(Tier::Exact, exact), (Tier::Completion, completions)];
+        groups.extend(self.extra.iter().cloned());
+        self.candidates = self.engine.ranking().arrange(groups).collect();
+        Ok(())
+    }
+
+    /// The sentence of the HMM model and the words covering only the leading
+    /// segments of the input, when the ranking policy shows them.
+    fn load_extra(&mut self) -> Result<(), LiushuError> {
+        self.extra.clear();
+        if self.input.is_empty() {
+            return Ok(());
+        }
+
+        let code = &self.input[..self.lookup_end()];
+        let ranking = self.engine.ranking();
+        let segments = self.engine.segment(code);
+        if segments.len() < 2 {
+            return Ok(());
+        }
+
+        let mut predicted = vec![];
+        if ranking.contains(Tier::Predicted) {
+            if let Some(sentence) = self.engine.sentence(code)? {
+                predicted.push(Candidate {
+                    text: sentence,
+                    code: code.to_string(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Words covering only the leading segments, longest first
+        let mut partial = vec![];
+        if ranking.contains(Tier::Fuzzy) {
+            let mut prefix_end = 0;
+            let mut prefixes = vec![];
+            for segment in &segments[..segments.len() - 1] {
+                prefix_end += segment.len();
+                prefixes.push(&code[..prefix_end]);
+            }
+            for prefix in prefixes.into_iter().rev() {
+                partial.extend(self.engine.search_exact(prefix));
+            }
+        }
+
+        self.extra = vec![(Tier::Predicted, predicted), (Tier::Fuzzy, partial)];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dict::{DictItem, Dictionary, Encoder, ReverseIndex};
+    use crate::engine::reverse_lookup::{ReverseLookup, DEFAULT_PREFIX};
+    use crate::test_util::TempDir;
+    use crate::user_dict::UserDictionary;
+
+    fn test_dictionary() -> Dictionary {
+        let mut trie = Dictionary::new();
+        for (text, code, weight) in [
+            ("你", "ni", 100),
+            ("泥", "ni", 50),
+            ("拟", "ni", 30),
+            ("你好", "nihao", 80),
+        ] {
+            let item = DictItem {
+                text: text.to_string(),
+                code: code.to_string(),
+                weight,
+                comment: None,
+            };
+            match trie.get_mut(code) {
+                Some(items) => items.push(item),
+                None => {
+                    trie.insert(code, vec![item]);
+                }
+            }
+        }
+
+        trie
+    }
+
+    fn test_session() -> Session {
+        Session::new(Arc::new(Engine::from_dictionary(test_dictionary()))).with_page_size(2)
+    }
+
+    fn texts(state: &SessionState) -> Vec<&str> {
+        state.candidates.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_compose_and_commit() {
+        let mut session = test_session();
+
+        assert_eq!(
+            session.process_key(Key::Space).unwrap(),
+            KeyResult::unhandled()
+        );
+        assert_eq!(
+            session.process_key(Key::Char('n')).unwrap(),
+            KeyResult::handled()
+        );
+        session.process_key(Key::Char('i')).unwrap();
+
+        let state = session.state();
+        assert_eq!(state.preedit, "ni");
+        assert_eq!(state.cursor, 2);
+        // exact matches come before completions, whatever their weights
+        assert_eq!(texts(&state), vec!["你", "泥"]);
+        assert!(state.has_next_page);
+
+        session.process_key(Key::PageDown).unwrap();
+        assert_eq!(texts(&session.state()), vec!["拟", "你好"]);
+
+        assert_eq!(
+            session.process_key(Key::Char('2')).unwrap(),
+            KeyResult::commit("你好".to_string())
+        );
+        assert_eq!(session.state(), SessionState::default());
+    }
+
+    #[test]
+    fn test_digit_after_commit() {
+        let mut session = test_session();
+        assert!(!session.engine().prediction_enabled());
+
+        session.input("ni").unwrap();
+        assert_eq!(
+            session.process_key(Key::Space).unwrap(),
+            KeyResult::commit("你".to_string())
+        );
+        // goes to the application instead of selecting a prediction
+        assert_eq!(
+            session.process_key(Key::Char('1')).unwrap(),
+            KeyResult::unhandled()
+        );
+        assert_eq!(session.state(), SessionState::default());
+    }
+
+    #[test]
+    fn test_predictions() {
+        let dir = TempDir::new("predictions");
+        let engine = Engine::from_dictionary(test_dictionary())
+            .with_user_dict(UserDictionary::open(dir.join("user.redb")).unwrap());
+        engine.set_prediction(true);
+        let mut session = Session::new(Arc::new(engine)).with_page_size(5);
+
+        // 拟 is learned as the word after 你
+        session.input("ni").unwrap();
+        assert_eq!(session.select(0).unwrap().as_deref(), Some("你"));
+        session.input("ni").unwrap();
+        assert_eq!(session.select(2).unwrap().as_deref(), Some("拟"));
+
+        session.clear();
+        session.input("ni").unwrap();
+        session.select(0).unwrap();
+        let state = session.state();
+        assert_eq!(state.preedit, "");
+        assert_eq!(texts(&state), vec!["拟"]);
+        assert_eq!(
+            session.process_key(Key::Char('1')).unwrap(),
+            KeyResult::commit("拟".to_string())
+        );
+
+        // other keys dismiss predictions and go to the application
+        session.input("ni").unwrap();
+        session.select(0).unwrap();
+        assert_eq!(
+            session.process_key(Key::Space).unwrap(),
+            KeyResult::unhandled()
+        );
+        assert!(session.state().candidates.is_empty());
+
+        session.engine().set_prediction(false);
+        session.input("ni").unwrap();
+        session.select(0).unwrap();
+        assert!(session.state().candidates.is_empty());
+    }
+
+    #[test]
+    fn test_editing_keys() {
+        let mut session = test_session();
+
+        session.input("nih").unwrap();
+        session.process_key(Key::Backspace).unwrap();
+        assert_eq!(session.state().preedit, "ni");
+
+        assert_eq!(
+            session.process_key(Key::Enter).unwrap(),
+            KeyResult::commit("ni".to_string())
+        );
+        assert_eq!(
+            session.process_key(Key::Backspace).unwrap(),
+            KeyResult::unhandled()
+        );
+
+        session.input("ni").unwrap();
+        session.process_key(Key::Escape).unwrap();
+        assert!(!session.is_composing());
+    }
+
+    #[test]
+    fn test_cursor_editing() {
+        let mut session = test_session();
+
+        session.input("nini").unwrap();
+        session.process_key(Key::Home).unwrap();
+        session.process_key(Key::Right).unwrap();
+        let state = session.state();
+        assert_eq!(state.cursor, 1);
+        // the caret is inside the first `ni`, so only that segment is looked up
+        assert_eq!(texts(&state), vec!["你", "泥"]);
+
+        session.clear();
+        session.input("nihao").unwrap();
+        session.process_key(Key::Home).unwrap();
+        session.process_key(Key::Right).unwrap();
+        session.process_key(Key::Right).unwrap();
+        session.process_key(Key::Delete).unwrap();
+        assert_eq!(session.state().preedit, "niao");
+        session.process_key(Key::Char('h')).unwrap();
+        assert_eq!(session.state().preedit, "nihao");
+        assert_eq!(session.state().cursor, 3);
+
+        session.process_key(Key::Left).unwrap();
+        session.process_key(Key::Backspace).unwrap();
+        assert_eq!(session.state().preedit, "nhao");
+        assert_eq!(session.state().cursor, 1);
+
+        session.process_key(Key::End).unwrap();
+        assert_eq!(session.state().cursor, 4);
+    }
+
+    #[test]
+    fn test_select_before_caret() {
+        let mut session = test_session();
+
+        session.input("nini").unwrap();
+        session.process_key(Key::Home).unwrap();
+        session.process_key(Key::Right).unwrap();
+        assert_eq!(
+            session.process_key(Key::Space).unwrap(),
+            KeyResult::handled()
+        );
+
+        let state = session.state();
+        assert_eq!(state.preedit, "你ni");
+        assert_eq!(state.confirmed, 1);
+        assert_eq!(state.cursor, 3);
+
+        assert_eq!(
+            session.process_key(Key::Space).unwrap(),
+            KeyResult::commit("你你".to_string())
+        );
+        assert!(!session.is_composing());
+    }
+
+    #[test]
+    fn test_partial_commit() {
+        let mut session = test_session();
+
+        session.input("nini").unwrap();
+        // nothing matches the whole input, the leading `ni` can be converted alone
+        assert_eq!(texts(&session.state()), vec!["你", "泥"]);
+
+        session.process_key(Key::Char('2')).unwrap();
+        assert_eq!(session.state().preedit, "泥ni");
+
+        // backspace at the start of the input reopens the confirmed segment
+        session.process_key(Key::Home).unwrap();
+        session.process_key(Key::Backspace).unwrap();
+        let state = session.state();
+        assert_eq!(state.preedit, "nini");
+        assert_eq!(state.confirmed, 0);
+        assert_eq!(state.cursor, 2);
+
+        session.process_key(Key::End).unwrap();
+        session.process_key(Key::Char('1')).unwrap();
+        assert_eq!(
+            session.process_key(Key::Enter).unwrap(),
+            KeyResult::commit("你ni".to_string())
+        );
+    }
+
+    #[test]
+    fn test_learn_encoded_phrase() {
+        let mut trie = Dictionary::new();
+        let mut codes = ReverseIndex::new();
+        for (text, code) in [("工", "aaaa"), ("人", "wwww")] {
+            let item = DictItem {
+                text: text.to_string(),
+                code: code.to_string(),
+                weight: 1,
+                comment: None,
+            };
+            trie.insert(code, vec![item]);
+            codes.insert(text.to_string(), vec![code.to_string()]);
+        }
+        let dir = TempDir::new("encoded");
+        let path = dir.join("user.redb");
+        let encoder = Encoder::from_config(&["2:AaAbBaBb".to_string()]).unwrap();
+        let engine = Engine::from_dictionary(trie)
+            .with_user_dict(UserDictionary::open(&path).unwrap())
+            .with_encoder(encoder, codes);
+        let mut session = Session::new(Arc::new(engine));
+
+        // learnt by the code of the phrase, not the typed one
+        session.input("aaaawwww").unwrap();
+        session.select(0).unwrap();
+        assert_eq!(session.select(0).unwrap().as_deref(), Some("工人"));
+        let user_dict = session.engine().user_dict().unwrap();
+        assert_eq!(user_dict.frequency("aaww", "工人"), 1);
+        assert_eq!(user_dict.frequency("aaaawwww", "工人"), 0);
+    }
+
+    #[test]
+    fn test_learn_reverse_lookup() {
+        let pinyin = test_dictionary();
+        let mut shape = Dictionary::new();
+        shape.insert(
+            "wq",
+            vec![DictItem {
+                text: "你".to_string(),
+                code: "wq".to_string(),
+                weight: 1,
+                comment: None,
+            }],
+        );
+        let index = ReverseIndex::from([("你".to_string(), vec!["wq".to_string()])]);
+        let dir = TempDir::new("lookup");
+        let path = dir.join("user.redb");
+        let engine = Engine::from_dictionary(shape)
+            .with_user_dict(UserDictionary::open(&path).unwrap())
+            .with_reverse_lookup(ReverseLookup::new(DEFAULT_PREFIX, pinyin, index));
+        let engine = Arc::new(engine);
+        let user_dict = engine.user_dict().unwrap();
+        let mut session = Session::new(engine.clone());
+
+        session.input("`ni").unwrap();
+        assert_eq!(session.select(0).unwrap().as_deref(), Some("你"));
+        assert_eq!(user_dict.frequency("wq", "你"), 1);
+        assert_eq!(user_dict.frequency("`ni", "你"), 0);
+        // no primary code, not learnt
+        session.input("`ni").unwrap();
+        assert_eq!(session.select(1).unwrap().as_deref(), Some("泥"));
+        assert_eq!(user_dict.frequency("`ni", "泥"), 0);
+    }
+
+    #[test]
+    fn test_punctuation() {
+        let mut session = test_session();
+
+        assert_eq!(
+            session.process_key(Key::Char(',')).unwrap(),
+            KeyResult::commit("，".to_string())
+        );
+
+        session.input("nini").unwrap();
+        assert_eq!(
+            session.process_key(Key::Char('.')).unwrap(),
+            KeyResult::commit("你你。".to_string())
+        );
+
+        assert_eq!(
+            session.process_key(Key::Char('"')).unwrap(),
+            KeyResult::commit("“".to_string())
+        );
+        assert_eq!(
+            session.process_key(Key::Char('"')).unwrap(),
+            KeyResult::commit("”".to_string())
+        );
+
+        session.process_key(Key::TogglePunctuation).unwrap();
+        assert!(!session.state().full_width_punct);
+        assert_eq!(
+            session.process_key(Key::Char(',')).unwrap(),
+            KeyResult::unhandled()
+        );
+        session.input("ni").unwrap();
+        assert_eq!(
+            session.process_key(Key::Char(',')).unwrap(),
+            KeyResult::commit("你,".to_string())
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut symbols = Dictionary::new();
+        for (code, texts) in [("nihao", vec!["👋"]), ("/jt", vec!["→", "←"])] {
+            let items = texts
+                .into_iter()
+                .map(|text| DictItem {
+                    text: text.to_string(),
+                    code: code.to_string(),
+                    weight: 0,
+                    comment: None,
+                })
+                .collect();
+            symbols.insert(code, items);
+        }
+        let engine = Engine::from_dictionary(test_dictionary()).with_symbols(symbols, 1);
+        let mut session = Session::new(Arc::new(engine)).with_page_size(2);
+
+        session.input("nihao").unwrap();
+        assert_eq!(texts(&session.state()), vec!["你好", "👋"]);
+        session.clear();
+
+        session.process_key(Key::Char('/')).unwrap();
+        session.input("j").unwrap();
+        assert_eq!(texts(&session.state()), vec!["→", "←"]);
+        assert_eq!(
+            session.process_key(Key::Char('2')).unwrap(),
+            KeyResult::commit("←".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ascii_mode() {
+        let mut session = test_session();
+
+        session.input("ni").unwrap();
+        assert_eq!(
+            session.process_key(Key::Shift).unwrap(),
+            KeyResult::commit("ni".to_string())
+        );
+        assert!(session.state().ascii_mode);
+        assert_eq!(
+            session.process_key(Key::Char('n')).unwrap(),
+            KeyResult::unhandled()
+        );
+
+        session.process_key(Key::Shift).unwrap();
+        assert_eq!(
+            session.process_key(Key::Char('n')).unwrap(),
+            KeyResult::handled()
+        );
+    }
+
+    #[test]
+    fn test_full_width() {
+        let mut session = test_session();
+
+        session.process_key(Key::ToggleFullWidth).unwrap();
+        assert!(session.state().full_width);
+        // keys the Chinese mode doesn't compose with are converted too
+        assert_eq!(
+            session.process_key(Key::Char('1')).unwrap(),
+            KeyResult::commit("１".to_string())
+        );
+
+        session.process_key(Key::Shift).unwrap();
+        assert_eq!(
+            session.process_key(Key::Char('A')).unwrap(),
+            KeyResult::commit("Ａ".to_string())
+        );
+        assert_eq!(
+            session.process_key(Key::Space).unwrap(),
+            KeyResult::commit("\u{3000}".to_string())
+        );
+
+        session.process_key(Key::ToggleFullWidth).unwrap();
+        assert_eq!(
+            session.process_key(Key::Char('A')).unwrap(),
+            KeyResult::unhandled()
+        );
+    }
+}
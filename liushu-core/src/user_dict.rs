@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{PoisonError, RwLock};
+
+use patricia_tree::StringPatriciaMap;
+use redb::{Database, ReadableTable, TableDefinition};
+
+use crate::engine::candidates::Candidate;
+use crate::error::LiushuError;
+
+const USER_TABLE: TableDefinition<(&str, &str), u64> = TableDefinition::new("user_words");
+const NEXT_TABLE: TableDefinition<(&str, &str), u64> = TableDefinition::new("user_next_words");
+
+/// Words committed by the user, counted per code, and the words committed
+/// right after them.
+///
+/// Counts are persisted in a redb database and mirrored in memory so that
+/// lookups don't touch the disk.
+#[derive(Debug)]
+pub struct UserDictionary {
+    db: Database,
+    words: RwLock<StringPatriciaMap<HashMap<String, u64>>>,
+    next_words: RwLock<HashMap<String, HashMap<String, u64>>>,
+}
+
+impl UserDictionary {
+    /// Open the user dictionary, creating it if it doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LiushuError> {
+        let db = Database::create(path)?;
+
+        let write_txn = db.begin_write()?;
+        write_txn.open_table(USER_TABLE)?;
+        write_txn.open_table(NEXT_TABLE)?;
+        write_txn.commit()?;
+
+        let mut words: StringPatriciaMap<HashMap<String, u64>> = StringPatriciaMap::new();
+        {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(USER_TABLE)?;
+            for result in table.iter()? {
+                let (key, value) = result?;
+                let (code, text) = key.value();
+                if words.get(code).is_none() {
+                    words.insert(code, HashMap::new());
+                }
+                if let Some(entry) = words.get_mut(code) {
+                    entry.insert(text.to_string(), value.value());
+                }
+            }
+        }
+
+        let mut next_words: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(NEXT_TABLE)?;
+            for result in table.iter()? {
+                let (key, value) = result?;
+                let (prev, text) = key.value();
+                next_words
+                    .entry(prev.to_string())
+                    .or_default()
+                    .insert(text.to_string(), value.value());
+            }
+        }
+
+        Ok(Self {
+            db,
+            words: RwLock::new(words),
+            next_words: RwLock::new(next_words),
+        })
+    }
+
+    /// Record that `text` was committed for `code`.
+    pub fn learn(&self, code: &str, text: &str) -> Result<(), LiushuError> {
+        if code.is_empty() || text.is_empty() {
+            return Ok(());
+        }
+
+        let mut words = self.words.write().unwrap_or_else(PoisonError::into_inner);
+        if words.get(code).is_none() {
+            words.insert(code, HashMap::new());
+        }
+        let count = match words.get_mut(code) {
+            Some(entry) => {
+                let count = entry.entry(text.to_string()).or_insert(0);
+                *count += 1;
+                *count
+            }
+            None => return Ok(()),
+        };
+
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(USER_TABLE)?;
+            table.insert((code, text), count)?;
+        }
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Record that `text` was committed right after `prev`.
+    pub fn learn_next(&self, prev: &str, text: &str) -> Result<(), LiushuError> {
+        if prev.is_empty() || text.is_empty() {
+            return Ok(());
+        }
+
+        let count = {
+            let mut next_words = self
+                .next_words
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            let count = next_words
+                .entry(prev.to_string())
+                .or_default()
+                .entry(text.to_string())
+                .or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(NEXT_TABLE)?;
+            table.insert((prev, text), count)?;
+        }
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Words the user committed right after `prev`, most used first.
+    pub fn next_words(&self, prev: &str) -> Vec<(String, u64)> {
+        let next_words = self
+            .next_words
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut words: Vec<(String, u64)> = next_words
+            .get(prev)
+            .map(|entry| {
+                entry
+                    .iter()
+                    .map(|(text, &count)| (text.clone(), count))
+                    .collect()
+            })
+            .unwrap_or_default();
+        words.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        words
+    }
+
+    pub fn frequency(&self, code: &str, text: &str) -> u64 {
+        let words = self.words.read().unwrap_or_else(PoisonError::into_inner);
+        words
+            .get(code)
+            .and_then(|entry| entry.get(text))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Move the words the user committed under `code` (or a longer code) to
+    /// the front, most used first, adding the ones not found by the
+    /// dictionary.
+    pub fn rerank(&self, code: &str, candidates: &mut Vec<Candidate>) {
+        if code.is_empty() {
+            return;
+        }
+
+        let mut counts: HashMap<String, (String, u64)> = HashMap::new();
+        {
+            let words = self.words.read().unwrap_or_else(PoisonError::into_inner);
+            for (word_code, entry) in words.iter_prefix(code) {
+                for (text, &count) in entry {
+                    let best = counts.entry(text.clone()).or_insert((word_code.clone(), 0));
+                    if count > best.1 {
+                        *best = (word_code.clone(), count);
+                    }
+                }
+            }
+        }
+        if counts.is_empty() {
+            return;
+        }
+
+        let mut missing: Vec<Candidate> = counts
+            .iter()
+            .filter(|(text, _)| !candidates.iter().any(|c| &c.text == *text))
+            .map(|(text, (word_code, _))| {
+                Candidate {
+                    text: text.clone(),
+                    code: word_code.clone(),
+                    ..Default::default()
+                }
+                .with_code_hint(code)
+            })
+            .collect();
+        missing.sort_by(|a, b| a.text.cmp(&b.text));
+        candidates.extend(missing);
+
+        candidates.sort_by_key(|c| {
+            std::cmp::Reverse(counts.get(&c.text).map(|(_, count)| *count).unwrap_or(0))
+        });
+    }
+}
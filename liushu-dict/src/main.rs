@@ -1,5 +1,12 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
-use liushu_core::dict::build;
+use liushu_core::config::Config;
+use liushu_core::dict::{
+    add, build_if_changed, build_symbols, build_with, merge, open, read_header, read_version,
+    remove, BuildOptions, Encoder, FormulaMeta, SourceFormat, FORMAT_VERSION,
+};
+use liushu_core::error::LiushuError;
 
 #[derive(Parser)]
 #[command(version)]
@@ -10,33 +17,232 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Build dictionary
+    /// Build dictionary, unless it was built from the same sources
     Build {
         #[arg(short, long)]
         inputs: Vec<String>,
 
         #[arg(short, long)]
         output: String,
+
+        /// Build even if the sources didn't change
+        #[arg(short, long)]
+        force: bool,
+
+        /// Id of the formula the dictionary is for, other formulas refuse it
+        #[arg(long)]
+        formula: Option<String>,
+
+        /// Display name of the formula
+        #[arg(long, requires = "formula")]
+        name: Option<String>,
+
+        /// Config to read the name and encoder rules of the formula from,
+        /// for generating the codes of phrases without one
+        #[arg(long, requires = "formula")]
+        config: Option<String>,
+
+        /// Columns of inputs without header row, e.g. `text,code,weight` or
+        /// `text` for word lists to generate the pinyin of. Given once for all
+        /// inputs or once per input
+        #[arg(short, long)]
+        columns: Vec<String>,
+
+        /// Weight of entries without one
+        #[arg(long, default_value_t = 0)]
+        default_weight: u32,
+
+        /// Encoding of the inputs, e.g. `gbk`
+        #[arg(short, long, default_value = "utf-8")]
+        encoding: String,
+
+        /// Heteronym dictionary for generating pinyin, see `liushu-ngram`
+        #[arg(long)]
+        heteronyms: Option<String>,
+    },
+    /// Print the header of a built dictionary
+    Info {
+        #[arg(short, long)]
+        dict: String,
+    },
+    /// Add or replace the entries of TSV patches in a built dictionary
+    Add {
+        #[arg(short, long)]
+        dict: String,
+
+        #[arg(short, long)]
+        inputs: Vec<String>,
+    },
+    /// Remove the entries listed by TSV patches from a built dictionary
+    Remove {
+        #[arg(short, long)]
+        dict: String,
+
+        #[arg(short, long)]
+        inputs: Vec<String>,
+    },
+    /// Merge built dictionaries, later ones take precedence
+    Merge {
+        #[arg(short, long)]
+        inputs: Vec<String>,
+
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Build emoji or symbol table
+    Symbols {
+        #[arg(short, long)]
+        inputs: Vec<String>,
+
+        #[arg(short, long)]
+        output: String,
     },
 }
 
 fn main() {
     let cli = Cli::parse();
+    if let Err(err) = run(&cli) {
+        eprintln!("{}", err.full_message());
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), LiushuError> {
     match &cli.command {
-        Some(Commands::Build { inputs, output }) => {
-            build(inputs, output).unwrap();
+        Some(Commands::Build {
+            inputs,
+            output,
+            force,
+            formula,
+            name,
+            config,
+            columns,
+            default_weight,
+            encoding,
+            heteronyms,
+        }) => {
+            let format = SourceFormat::default()
+                .with_default_weight(*default_weight)
+                .with_encoding(encoding)?;
+            let formats = if columns.is_empty() {
+                vec![format]
+            } else {
+                columns
+                    .iter()
+                    .map(|spec| format.clone().with_columns(spec))
+                    .collect::<Result<_, _>>()?
+            };
+            let (mut name, mut encoder) = (name.clone(), Encoder::default());
+            if let (Some(path), Some(id)) = (config, formula) {
+                let config = Config::load(path)?;
+                let Some(formula) = config.formula(id) else {
+                    return Err(LiushuError::Config(format!(
+                        "no formula {} in {}",
+                        id, path
+                    )));
+                };
+                name = name.or_else(|| formula.name.clone());
+                encoder = Encoder::from_config(&formula.encoder)?;
+            }
+            let options = BuildOptions {
+                formula: formula.as_ref().map(|id| FormulaMeta {
+                    id: id.clone(),
+                    name,
+                }),
+                formats,
+                heteronyms: heteronyms.as_ref().map(PathBuf::from),
+                encoder,
+            };
+            if *force {
+                build_with(inputs, output, &options)?;
+            } else if !build_if_changed(inputs, output, &options)? {
+                println!("{} is up to date", output);
+            }
+        }
+        Some(Commands::Info { dict }) => {
+            let version = read_version(dict)?;
+            println!("format version: {}", version);
+            if version != FORMAT_VERSION {
+                println!(
+                    "expected format version {}, rebuild it with `liushu-dict build`",
+                    FORMAT_VERSION
+                );
+                return Ok(());
+            }
+            let header = read_header(dict)?;
+            println!("built at: {}", format_timestamp(header.built_at));
+            match &header.formula {
+                Some(FormulaMeta {
+                    id,
+                    name: Some(name),
+                }) => println!("formula: {} ({})", id, name),
+                Some(FormulaMeta { id, name: None }) => println!("formula: {}", id),
+                None => println!("formula: any"),
+            }
+            println!("source hash: {}", header.source_hash);
+            println!("sources:");
+            for source in &header.sources {
+                println!("  {}  {}", source.hash, source.path);
+            }
+            let (_, trie) = open(dict)?;
+            let entries: usize = trie.values().map(Vec::len).sum();
+            println!("codes: {}", trie.len());
+            println!("entries: {}", entries);
+        }
+        Some(Commands::Add { dict, inputs }) => {
+            add(dict, inputs)?;
+        }
+        Some(Commands::Remove { dict, inputs }) => {
+            remove(dict, inputs)?;
+        }
+        Some(Commands::Merge { inputs, output }) => {
+            merge(inputs, output)?;
+        }
+        Some(Commands::Symbols { inputs, output }) => {
+            build_symbols(inputs, output)?;
         }
         None => {}
     }
+    Ok(())
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC` of seconds since the Unix epoch.
+fn format_timestamp(secs: u64) -> String {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Cli;
+    use crate::{format_timestamp, Cli};
     use clap::CommandFactory;
 
     #[test]
     fn verify_cli() {
         Cli::command().debug_assert()
     }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1709251199), "2024-02-29 23:59:59 UTC");
+    }
 }
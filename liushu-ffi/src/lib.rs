@@ -1,6 +1,299 @@
-uniffi::include_scaffolding!("lib");
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
+use thiserror::Error;
+
+pub use liushu_core::config::{Config, Formula};
 pub use liushu_core::engine::candidates::Candidate;
 use liushu_core::engine::segmentor::Segmentor;
 pub use liushu_core::engine::{Engine, InputMethodEngine};
-pub use liushu_core::error::LiushuError;
+use liushu_core::error::LiushuError as CoreError;
+use liushu_core::session;
+use liushu_core::user_dict::UserDictionary;
+
+/// The generated scaffolding, which matches unit enum variants with struct
+/// patterns.
+#[allow(unknown_lints, clippy::unneeded_struct_pattern)]
+mod scaffolding {
+    use super::*;
+
+    uniffi::include_scaffolding!("lib");
+}
+
+pub use scaffolding::uniffi_reexport_hack;
+
+/// Errors of the bindings, keeping the fields callers can act on. `reason`
+/// is the message followed by those of the sources, which don't cross the
+/// FFI.
+#[derive(Debug, Error)]
+pub enum LiushuError {
+    #[error("{reason}")]
+    Io { reason: String },
+    #[error("{reason}")]
+    DictFormat {
+        path: String,
+        /// 1-based line number, when the problem can be located
+        line: Option<u64>,
+        reason: String,
+    },
+    #[error("{reason}")]
+    DictVersion {
+        path: String,
+        found: u32,
+        expected: u32,
+        reason: String,
+    },
+    #[error("{reason}")]
+    ModelFormat { reason: String },
+    #[error("{reason}")]
+    Storage { reason: String },
+    #[error("{reason}")]
+    Config { reason: String },
+    #[error("{reason}")]
+    InvalidInput { reason: String },
+    #[error("{reason}")]
+    MalformedCorpus {
+        lines: u64,
+        malformed: u64,
+        max_errors: u64,
+        reason: String,
+    },
+}
+
+impl From<CoreError> for LiushuError {
+    fn from(err: CoreError) -> Self {
+        let reason = err.full_message();
+        match err {
+            CoreError::Io(_) => Self::Io { reason },
+            CoreError::DictFormat { path, line, .. } => Self::DictFormat {
+                path: path.display().to_string(),
+                line,
+                reason,
+            },
+            CoreError::DictVersion {
+                path,
+                found,
+                expected,
+            } => Self::DictVersion {
+                path: path.display().to_string(),
+                found,
+                expected,
+                reason,
+            },
+            CoreError::ModelFormat(_) => Self::ModelFormat { reason },
+            CoreError::Storage(_) => Self::Storage { reason },
+            CoreError::Config(_) => Self::Config { reason },
+            CoreError::InvalidInput(_) => Self::InvalidInput { reason },
+            CoreError::MalformedCorpus { report, max_errors } => Self::MalformedCorpus {
+                lines: report.lines as u64,
+                malformed: report.errors.len() as u64,
+                max_errors: max_errors as u64,
+                reason,
+            },
+        }
+    }
+}
+
+pub fn load_config(path: &str) -> Result<Config, CoreError> {
+    Config::load(path)
+}
+
+/// Engine constructors and methods with FFI friendly signatures.
+pub trait EngineExt: Sized {
+    fn load(
+        formula: &Formula,
+        data_dir: &str,
+        user_dict_path: Option<String>,
+    ) -> Result<Self, CoreError>;
+
+    fn sentence_candidates(&self, code: &str, top_k: u32) -> Result<Vec<Candidate>, CoreError>;
+
+    fn prediction_candidates(&self, text: &str, limit: u32) -> Result<Vec<Candidate>, CoreError>;
+
+    fn search_page(
+        &self,
+        code: &str,
+        page: u32,
+        page_size: u32,
+    ) -> Result<Vec<Candidate>, CoreError>;
+}
+
+impl EngineExt for Engine {
+    fn load(
+        formula: &Formula,
+        data_dir: &str,
+        user_dict_path: Option<String>,
+    ) -> Result<Self, CoreError> {
+        let engine = Engine::from_formula(formula, data_dir)?;
+        match user_dict_path {
+            Some(path) => Ok(engine.with_user_dict(UserDictionary::open(path)?)),
+            None => Ok(engine),
+        }
+    }
+
+    fn sentence_candidates(&self, code: &str, top_k: u32) -> Result<Vec<Candidate>, CoreError> {
+        self.sentences(code, top_k as usize)
+    }
+
+    fn prediction_candidates(&self, text: &str, limit: u32) -> Result<Vec<Candidate>, CoreError> {
+        self.predict(text, limit as usize)
+    }
+
+    fn search_page(
+        &self,
+        code: &str,
+        page: u32,
+        page_size: u32,
+    ) -> Result<Vec<Candidate>, CoreError> {
+        let (page, page_size) = (page as usize, page_size as usize);
+        Ok(self
+            .search_top(code, (page + 1) * page_size)?
+            .into_iter()
+            .skip(page * page_size)
+            .take(page_size)
+            .collect())
+    }
+}
+
+pub struct SessionState {
+    pub preedit: String,
+    pub confirmed: u32,
+    pub cursor: u32,
+    pub candidates: Vec<Candidate>,
+    pub page: u32,
+    pub has_prev_page: bool,
+    pub has_next_page: bool,
+    pub ascii_mode: bool,
+    pub full_width_punct: bool,
+    pub full_width: bool,
+}
+
+impl From<session::SessionState> for SessionState {
+    fn from(state: session::SessionState) -> Self {
+        Self {
+            preedit: state.preedit,
+            confirmed: state.confirmed as u32,
+            cursor: state.cursor as u32,
+            candidates: state.candidates,
+            page: state.page as u32,
+            has_prev_page: state.has_prev_page,
+            has_next_page: state.has_next_page,
+            ascii_mode: state.ascii_mode,
+            full_width_punct: state.full_width_punct,
+            full_width: state.full_width,
+        }
+    }
+}
+
+pub enum Key {
+    Char { text: String },
+    Space,
+    Enter,
+    Backspace,
+    Escape,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+    Shift,
+    TogglePunctuation,
+    ToggleFullWidth,
+}
+
+pub struct KeyResult {
+    pub handled: bool,
+    pub commit: Option<String>,
+}
+
+/// Composition state of one input field, see [`session::Session`].
+pub struct Session {
+    inner: Mutex<session::Session>,
+}
+
+impl Session {
+    pub fn new(engine: Arc<Engine>, page_size: u32) -> Self {
+        Self {
+            inner: Mutex::new(session::Session::new(engine).with_page_size(page_size as usize)),
+        }
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.lock().state().into()
+    }
+
+    pub fn process_key(&self, key: Key) -> Result<KeyResult, CoreError> {
+        let key = match key {
+            Key::Char { text } => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => session::Key::Char(ch),
+                    _ => {
+                        return Err(CoreError::InvalidInput(format!(
+                            "expect a single char, got {:?}",
+                            text
+                        )))
+                    }
+                }
+            }
+            Key::Space => session::Key::Space,
+            Key::Enter => session::Key::Enter,
+            Key::Backspace => session::Key::Backspace,
+            Key::Escape => session::Key::Escape,
+            Key::PageUp => session::Key::PageUp,
+            Key::PageDown => session::Key::PageDown,
+            Key::Left => session::Key::Left,
+            Key::Right => session::Key::Right,
+            Key::Home => session::Key::Home,
+            Key::End => session::Key::End,
+            Key::Delete => session::Key::Delete,
+            Key::Shift => session::Key::Shift,
+            Key::TogglePunctuation => session::Key::TogglePunctuation,
+            Key::ToggleFullWidth => session::Key::ToggleFullWidth,
+        };
+
+        let result = self.lock().process_key(key)?;
+        Ok(KeyResult {
+            handled: result.handled,
+            commit: result.commit,
+        })
+    }
+
+    pub fn input(&self, text: &str) -> Result<SessionState, CoreError> {
+        let mut session = self.lock();
+        session.input(text)?;
+        Ok(session.state().into())
+    }
+
+    pub fn backspace(&self) -> Result<SessionState, CoreError> {
+        let mut session = self.lock();
+        session.backspace()?;
+        Ok(session.state().into())
+    }
+
+    /// Commit the `index`-th candidate of the current page, returning its text.
+    pub fn select(&self, index: u32) -> Result<Option<String>, CoreError> {
+        self.lock().select(index as usize)
+    }
+
+    pub fn next_page(&self) -> Result<SessionState, CoreError> {
+        let mut session = self.lock();
+        session.next_page()?;
+        Ok(session.state().into())
+    }
+
+    pub fn prev_page(&self) -> SessionState {
+        let mut session = self.lock();
+        session.prev_page();
+        session.state().into()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, session::Session> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use camino::Utf8PathBuf;
+use liushu_core::dict::{build, FORMAT_VERSION, MAGIC};
+use liushu_core::test_util::TempDir;
+use liushu_core::user_dict::UserDictionary;
+use liushu_ffi::{Engine, Key, LiushuError, Session};
+
+fn test_engine(dir: &Path) -> Engine {
+    let source = dir.join("dict.tsv");
+    fs::write(
+        &source,
+        "text\tcode\tweight\tcomment\n\
+         你\tni\t100\t\n\
+         泥\tni\t50\t\n\
+         拟\tni\t30\t\n\
+         妮\tni\t10\t\n\
+         你好\tnihao\t80\t\n",
+    )
+    .unwrap();
+    let trie = dir.join("dict.trie");
+    build(&[source], &trie).unwrap();
+
+    Engine::new(&trie)
+        .unwrap()
+        .with_user_dict(UserDictionary::open(dir.join("user.redb")).unwrap())
+}
+
+#[test]
+fn test_generate_kotlin_bindings() {
+    let out_dir = TempDir::new("ffi-kotlin");
+    let udl = Utf8PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.udl"));
+    let out = Utf8PathBuf::from_path_buf(out_dir.to_path_buf()).unwrap();
+    uniffi_bindgen::generate_bindings(&udl, None, vec!["kotlin"], Some(&out), None, false).unwrap();
+
+    let source = fs::read_to_string(out_dir.join("com/elliot00/liushu/uniffi/liushu.kt")).unwrap();
+    for expected in [
+        "fun `loadConfig`(`path`: String): Config",
+        "data class Formula",
+        "data class SessionState",
+        "class Session(",
+        "fun `processKey`(`key`: Key): KeyResult",
+        "fun `load`(`formula`: Formula, `dataDir`: String, `userDictPath`: String?): Engine",
+        "fun `sentenceCandidates`(`code`: String, `topK`: UInt): List<Candidate>",
+        "fun `searchPage`(`code`: String, `page`: UInt, `pageSize`: UInt): List<Candidate>",
+        "fun `learn`(`code`: String, `text`: String)",
+        "fun `setConversion`(`enabled`: Boolean)",
+        "class DictFormat(",
+        "val `line`: ULong?",
+        "val `found`: UInt",
+        "class InvalidInput(",
+    ] {
+        assert!(source.contains(expected), "missing `{}`", expected);
+    }
+}
+
+#[test]
+fn test_error_message() {
+    let dir = TempDir::new("ffi-error");
+    let path = dir.join("dict.trie");
+    fs::write(&path, "not a dictionary").unwrap();
+
+    // the sources only reach the bindings through the reason
+    let err = LiushuError::from(Engine::new(&path).unwrap_err());
+    let LiushuError::DictFormat {
+        path: failed,
+        line,
+        reason,
+    } = &err
+    else {
+        panic!("expected a format error, got {:?}", err);
+    };
+    assert_eq!(failed, path.to_str().unwrap());
+    assert_eq!(*line, None);
+    assert!(reason.contains("not a liushu dictionary"));
+    assert_eq!(err.to_string(), *reason);
+
+    let mut stale = MAGIC.to_vec();
+    stale.extend(1u32.to_le_bytes());
+    fs::write(&path, stale).unwrap();
+    let err = LiushuError::from(Engine::new(&path).unwrap_err());
+    assert!(matches!(
+        err,
+        LiushuError::DictVersion {
+            found: 1,
+            expected: FORMAT_VERSION,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_session_paging_and_learning() {
+    let dir = TempDir::new("ffi-session");
+    let session = Session::new(Arc::new(test_engine(&dir)), 2);
+
+    let state = session.input("ni").unwrap();
+    assert_eq!(state.preedit, "ni");
+    let texts: Vec<_> = state.candidates.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, vec!["你", "泥"]);
+    assert!(!state.has_prev_page);
+    assert!(state.has_next_page);
+
+    let state = session.next_page().unwrap();
+    assert_eq!(state.page, 1);
+    let texts: Vec<_> = state.candidates.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, vec!["拟", "妮"]);
+
+    assert_eq!(session.select(0).unwrap().as_deref(), Some("拟"));
+    assert_eq!(session.state().preedit, "");
+
+    // the committed candidate now comes first
+    let state = session.input("n").unwrap();
+    assert_eq!(state.candidates[0].text, "拟");
+
+    let state = session.backspace().unwrap();
+    assert!(state.candidates.is_empty());
+    assert_eq!(session.select(0).unwrap(), None);
+}
+
+#[test]
+fn test_session_keys() {
+    let dir = TempDir::new("ffi-keys");
+    let session = Session::new(Arc::new(test_engine(&dir)), 2);
+
+    for ch in ["n", "i"] {
+        let result = session
+            .process_key(Key::Char {
+                text: ch.to_string(),
+            })
+            .unwrap();
+        assert!(result.handled);
+    }
+    assert_eq!(session.state().cursor, 2);
+    assert!(session.process_key(Key::Left).unwrap().handled);
+    assert_eq!(session.state().cursor, 1);
+    session.process_key(Key::End).unwrap();
+
+    let result = session.process_key(Key::Space).unwrap();
+    assert_eq!(result.commit.as_deref(), Some("你"));
+
+    assert!(!session.process_key(Key::Enter).unwrap().handled);
+    let result = session
+        .process_key(Key::Char {
+            text: "?".to_string(),
+        })
+        .unwrap();
+    assert_eq!(result.commit.as_deref(), Some("？"));
+    assert!(session.state().full_width_punct);
+
+    session.process_key(Key::ToggleFullWidth).unwrap();
+    assert!(session.state().full_width);
+    let result = session
+        .process_key(Key::Char {
+            text: "1".to_string(),
+        })
+        .unwrap();
+    assert_eq!(result.commit.as_deref(), Some("１"));
+
+    assert!(session
+        .process_key(Key::Char {
+            text: "ni".to_string()
+        })
+        .is_err());
+}
@@ -1,7 +1,12 @@
+use std::num::NonZeroUsize;
+
 use clap::{Parser, Subcommand};
 use redb::Database;
 
-use liushu_core::hmm::{pinyin_to_sentence, train_to_db};
+use liushu_core::corpus::{prepare_corpus, PinyinAnnotator};
+use liushu_core::hmm::decoder::{Beam, Decoder, DecoderConfig};
+use liushu_core::hmm::eval::evaluate;
+use liushu_core::hmm::train_to_db;
 
 #[derive(Parser)]
 #[command(version)]
@@ -12,6 +17,20 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Annotate raw Chinese text with pinyin to produce a training corpus
+    Prepare {
+        #[arg(short, long)]
+        input_path: String,
+
+        #[arg(short, long)]
+        output_path: String,
+
+        /// Phrase readings giving the syllables of heteronyms in those phrases,
+        /// other characters get their most common reading
+        #[arg(long)]
+        heteronym_path: Option<String>,
+    },
+
     /// Train a bigram language model on a text corpus
     Train {
         #[arg(short, long)]
@@ -19,6 +38,10 @@ enum Commands {
 
         #[arg(short, long)]
         output_path: String,
+
+        /// Skip up to this many malformed corpus lines instead of aborting
+        #[arg(long, default_value_t = 0)]
+        max_errors: usize,
     },
 
     /// Lookup the most probable hanzi sequence for the input using a trained model
@@ -27,24 +50,129 @@ enum Commands {
 
         #[arg(short, long)]
         model_path: String,
+
+        /// Keep at most this many states per syllable instead of searching exhaustively
+        #[arg(long)]
+        beam: Option<NonZeroUsize>,
+
+        /// Drop states scoring more than this (in log probability) below the best one
+        #[arg(long, requires = "beam")]
+        beam_threshold: Option<f64>,
+    },
+
+    /// Measure the accuracy of a trained model on an annotated corpus
+    Eval {
+        #[arg(short, long)]
+        corpus_path: String,
+
+        #[arg(short, long)]
+        model_path: String,
+
+        /// Count a line as hit when the expected text is among the best k conversions
+        #[arg(short = 'k', long, default_value_t = 5)]
+        top_k: usize,
+
+        /// Skip up to this many malformed corpus lines instead of aborting
+        #[arg(long, default_value_t = 0)]
+        max_errors: usize,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
     },
 }
 
 fn main() {
     let cli = Cli::parse();
     match &cli.command {
+        Some(Commands::Prepare {
+            input_path,
+            output_path,
+            heteronym_path,
+        }) => {
+            let annotator = match heteronym_path {
+                Some(path) => PinyinAnnotator::with_heteronyms(path).unwrap(),
+                None => PinyinAnnotator::new(),
+            };
+            prepare_corpus(input_path, output_path, &annotator).unwrap();
+        }
         Some(Commands::Train {
             corpus_path,
             output_path,
+            max_errors,
         }) => {
             let db = Database::create(output_path).unwrap();
-            train_to_db(corpus_path, &db).unwrap();
+            match train_to_db(corpus_path, &db, *max_errors) {
+                Ok(report) => {
+                    for err in &report.errors {
+                        println!("skipped line {}: {}", err.line, err.error);
+                    }
+                    println!("trained on {} of {} lines", report.valid, report.lines);
+                }
+                Err(err) => {
+                    eprintln!("{}", err.full_message());
+                    std::process::exit(1);
+                }
+            }
         }
-        Some(Commands::Lookup { inputs, model_path }) => {
-            let db = Database::open(model_path).unwrap();
-            let result = pinyin_to_sentence(inputs, &db).unwrap();
+        Some(Commands::Lookup {
+            inputs,
+            model_path,
+            beam,
+            beam_threshold,
+        }) => {
+            let config = DecoderConfig {
+                beam: beam.map(|width| Beam {
+                    width: width.get(),
+                    threshold: beam_threshold.unwrap_or(f64::INFINITY),
+                }),
+                ..Default::default()
+            };
+            let decoder = Decoder::with_config(Database::open(model_path).unwrap(), config);
+            let result = decoder.decode(inputs).unwrap();
             println!("result is {}", result);
         }
+        Some(Commands::Eval {
+            corpus_path,
+            model_path,
+            top_k,
+            max_errors,
+            json,
+        }) => {
+            let decoder = Decoder::open(model_path).unwrap();
+            let report = match evaluate(corpus_path, &decoder, *top_k, *max_errors) {
+                Ok(report) => report,
+                Err(err) => {
+                    eprintln!("{}", err.full_message());
+                    std::process::exit(1);
+                }
+            };
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            } else {
+                for line in &report.skipped_lines {
+                    println!("skipped malformed line {}", line);
+                }
+                for diff in &report.diffs {
+                    println!(
+                        "line {}: {}\n  expected {}\n  actual   {}",
+                        diff.line, diff.pinyin, diff.expected, diff.actual
+                    );
+                }
+                println!(
+                    "sentence accuracy {:.4} ({}/{})",
+                    report.sentence_accuracy, report.sentence_hits, report.sentences
+                );
+                println!(
+                    "char accuracy {:.4} ({} errors in {} chars)",
+                    report.char_accuracy, report.char_errors, report.chars
+                );
+                println!(
+                    "top-{} hit rate {:.4} ({}/{})",
+                    report.top_k, report.top_k_hit_rate, report.top_k_hits, report.sentences
+                );
+            }
+        }
         None => {}
     }
 }
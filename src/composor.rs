@@ -1,109 +1,180 @@
 use std::collections::HashSet;
+use std::sync::Arc;
 
-use liushu_core::engine::{candidates::Candidate, Engine, InputMethodEngine};
+use liushu_core::engine::Engine;
+use liushu_core::session::{Key, Session, SessionState};
 use wayland_client::{protocol::wl_keyboard, WEnum};
 
-use crate::keyboard::KeyboardProcessorResponse;
+use crate::keyboard::{
+    translate_key, KEY_DOT, KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_RIGHTCTRL, KEY_RIGHTSHIFT, KEY_SPACE,
+};
 
+/// Feeds `wl_keyboard` events into a [`Session`], keeping track of the keys
+/// it consumed so that their releases are not forwarded either.
+///
+/// Shift alone toggles ASCII mode when released, combined with another key it
+/// only shifts that key. Shift+Space toggles full-width mode and Ctrl+. the
+/// punctuation width.
 #[derive(Debug, Default)]
 pub struct Composor {
-    input: String,
-    engine: Engine,
-    candidates: Vec<Candidate>,
+    session: Session,
     handled_keys: HashSet<u32>,
+    shift: bool,
+    /// Whether a key was pressed while shift was held
+    shift_used: bool,
+    ctrl: bool,
+}
+
+pub enum ComposorResponse {
+    Handled {
+        commit: Option<String>,
+        state: SessionState,
+    },
+    Ignored,
+    Unhandled,
 }
 
 impl Composor {
-    pub fn with_engine(engine: Engine) -> Self {
+    pub fn with_engine(engine: Arc<Engine>) -> Self {
         Self {
-            engine,
+            session: Session::new(engine),
             ..Default::default()
         }
     }
 
-    pub fn process(&mut self, data: KeyboardProcessorResponse) -> KeyboardProcessorResponse {
-        match data {
-            KeyboardProcessorResponse::Unhandled(wl_keyboard::Event::Key {
-                key, state, ..
-            }) => match state {
-                WEnum::Value(wl_keyboard::KeyState::Pressed) => match key {
-                    14 => {
-                        if !self.input.is_empty() {
-                            self.handled_keys.insert(key);
-                            self.input.pop();
-                            if let Ok(res) = self.engine.search(&self.input) {
-                                self.candidates = res;
-                            }
-                            KeyboardProcessorResponse::Result(
-                                self.input.clone(),
-                                self.candidates.clone(),
-                            )
-                        } else {
-                            data
-                        }
+    pub fn process(&mut self, event: wl_keyboard::Event) -> ComposorResponse {
+        let wl_keyboard::Event::Key { key, state, .. } = event else {
+            return ComposorResponse::Unhandled;
+        };
+
+        match state {
+            WEnum::Value(wl_keyboard::KeyState::Pressed) => match key {
+                KEY_LEFTSHIFT | KEY_RIGHTSHIFT => {
+                    self.shift = true;
+                    self.shift_used = false;
+                    ComposorResponse::Unhandled
+                }
+                KEY_LEFTCTRL | KEY_RIGHTCTRL => {
+                    self.ctrl = true;
+                    ComposorResponse::Unhandled
+                }
+                KEY_DOT if self.ctrl => self.process_key(key, Key::TogglePunctuation),
+                _ if self.ctrl => ComposorResponse::Unhandled,
+                KEY_SPACE if self.shift => {
+                    self.shift_used = true;
+                    self.process_key(key, Key::ToggleFullWidth)
+                }
+                _ => {
+                    self.shift_used = self.shift;
+                    match translate_key(key, self.shift) {
+                        Some(session_key) => self.process_key(key, session_key),
+                        None => ComposorResponse::Unhandled,
                     }
-                    28 => {
-                        if self.input.is_empty() {
-                            data
-                        } else {
-                            self.handled_keys.insert(key);
-                            KeyboardProcessorResponse::DirectlyCommit
-                        }
+                }
+            },
+            WEnum::Value(wl_keyboard::KeyState::Released) => match key {
+                KEY_LEFTSHIFT | KEY_RIGHTSHIFT => {
+                    self.shift = false;
+                    if self.shift_used {
+                        return ComposorResponse::Unhandled;
                     }
-                    _ => data,
-                },
-                WEnum::Value(wl_keyboard::KeyState::Released) => {
-                    if self.handled_keys.contains(&key) {
-                        self.handled_keys.remove(&key);
-                        KeyboardProcessorResponse::Ignored
-                    } else {
-                        data
+                    // The release is forwarded too, as the press was
+                    match self.process_key(key, Key::Shift) {
+                        response @ ComposorResponse::Handled { .. } => {
+                            self.handled_keys.remove(&key);
+                            response
+                        }
+                        _ => ComposorResponse::Unhandled,
                     }
                 }
-                _ => data,
+                KEY_LEFTCTRL | KEY_RIGHTCTRL => {
+                    self.ctrl = false;
+                    ComposorResponse::Unhandled
+                }
+                _ if self.handled_keys.remove(&key) => ComposorResponse::Ignored,
+                _ => ComposorResponse::Unhandled,
             },
-            KeyboardProcessorResponse::Composing(spell_key) => {
-                let key_str = match spell_key {
-                    16 => "q",
-                    17 => "w",
-                    18 => "e",
-                    19 => "r",
-                    20 => "t",
-                    21 => "y",
-                    22 => "u",
-                    23 => "i",
-                    24 => "o",
-                    25 => "p",
-                    30 => "a",
-                    31 => "s",
-                    32 => "d",
-                    33 => "f",
-                    34 => "g",
-                    35 => "h",
-                    36 => "j",
-                    37 => "k",
-                    38 => "l",
-                    44 => "z",
-                    45 => "x",
-                    46 => "c",
-                    47 => "v",
-                    48 => "b",
-                    49 => "n",
-                    50 => "m",
-                    _ => "",
-                };
-                self.input.push_str(key_str);
-                if let Ok(res) = self.engine.search(&self.input) {
-                    self.candidates = res;
+            _ => ComposorResponse::Unhandled,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.session.clear();
+        self.session.reset_punctuation();
+    }
+
+    fn process_key(&mut self, key: u32, session_key: Key) -> ComposorResponse {
+        match self.session.process_key(session_key) {
+            Ok(result) if result.handled => {
+                self.handled_keys.insert(key);
+                ComposorResponse::Handled {
+                    commit: result.commit,
+                    state: self.session.state(),
                 }
-                KeyboardProcessorResponse::Result(self.input.clone(), self.candidates.clone())
             }
-            _ => data,
+            Ok(_) => ComposorResponse::Unhandled,
+            Err(err) => {
+                eprintln!("process key error: {}", err.full_message());
+                ComposorResponse::Unhandled
+            }
         }
     }
+}
 
-    pub fn clear(&mut self) {
-        self.input.clear();
-        self.candidates.clear();
+/// Text shown as the preedit: the typed code followed by the candidates of
+/// the page, each with the rest of its code, e.g. `ni 1.你 2.您~n`, and
+/// `[全角]` while full-width mode is on.
+///
+/// There is no candidate panel or status line, the protocol only has the
+/// preedit.
+pub fn preedit_text(state: &SessionState) -> String {
+    let mut parts = vec![state.preedit.clone()];
+    for (idx, candidate) in state.candidates.iter().enumerate() {
+        parts.push(match &candidate.code_hint {
+            Some(hint) => format!("{}.{}~{}", idx + 1, candidate.text, hint),
+            None => format!("{}.{}", idx + 1, candidate.text),
+        });
+    }
+    if state.full_width {
+        parts.push("[全角]".to_string());
+    }
+    parts.retain(|part| !part.is_empty());
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use liushu_core::engine::candidates::Candidate;
+
+    use super::*;
+
+    #[test]
+    fn test_preedit_text() {
+        let candidate = |text: &str, code_hint: Option<&str>| Candidate {
+            text: text.to_string(),
+            code_hint: code_hint.map(str::to_string),
+            ..Default::default()
+        };
+        let state = SessionState {
+            preedit: "ni".to_string(),
+            candidates: vec![candidate("你", None), candidate("您", Some("n"))],
+            ..Default::default()
+        };
+        assert_eq!(preedit_text(&state), "ni 1.你 2.您~n");
+        assert_eq!(preedit_text(&SessionState::default()), "");
+    }
+
+    #[test]
+    fn test_preedit_text_full_width() {
+        let state = SessionState {
+            full_width: true,
+            ..Default::default()
+        };
+        assert_eq!(preedit_text(&state), "[全角]");
+        let state = SessionState {
+            preedit: "ni".to_string(),
+            ..state
+        };
+        assert_eq!(preedit_text(&state), "ni [全角]");
     }
 }
@@ -1,70 +1,73 @@
-use std::collections::HashSet;
+use liushu_core::session::Key;
 
-use liushu_core::engine::candidates::Candidate;
-use wayland_client::{protocol::wl_keyboard, WEnum};
+pub const KEY_LEFTCTRL: u32 = 29;
+pub const KEY_LEFTSHIFT: u32 = 42;
+pub const KEY_RIGHTSHIFT: u32 = 54;
+pub const KEY_RIGHTCTRL: u32 = 97;
+pub const KEY_DOT: u32 = 52;
+pub const KEY_SPACE: u32 = 57;
 
-#[derive(Debug, Default)]
-pub struct KeyboardProcessor {
-    handled_keys: HashSet<u32>,
+/// Translate a Linux evdev key code into a session key, as typed on a US
+/// layout with or without shift.
+pub fn translate_key(code: u32, shift: bool) -> Option<Key> {
+    let key = match code {
+        1 => Key::Escape,
+        2..=11 if shift => Key::Char(b"!@#$%^&*()"[(code - 2) as usize] as char),
+        2..=10 => Key::Char(char::from(b'1' + (code - 2) as u8)),
+        11 => Key::Char('0'),
+        12 => Key::Char(if shift { '_' } else { '-' }),
+        13 => Key::Char(if shift { '+' } else { '=' }),
+        14 => Key::Backspace,
+        16 => letter('q', shift),
+        17 => letter('w', shift),
+        18 => letter('e', shift),
+        19 => letter('r', shift),
+        20 => letter('t', shift),
+        21 => letter('y', shift),
+        22 => letter('u', shift),
+        23 => letter('i', shift),
+        24 => letter('o', shift),
+        25 => letter('p', shift),
+        26 => Key::Char(if shift { '{' } else { '[' }),
+        27 => Key::Char(if shift { '}' } else { ']' }),
+        28 => Key::Enter,
+        30 => letter('a', shift),
+        31 => letter('s', shift),
+        32 => letter('d', shift),
+        33 => letter('f', shift),
+        34 => letter('g', shift),
+        35 => letter('h', shift),
+        36 => letter('j', shift),
+        37 => letter('k', shift),
+        38 => letter('l', shift),
+        39 => Key::Char(if shift { ':' } else { ';' }),
+        40 => Key::Char(if shift { '"' } else { '\'' }),
+        41 => Key::Char(if shift { '~' } else { '`' }),
+        KEY_LEFTSHIFT | KEY_RIGHTSHIFT => Key::Shift,
+        43 => Key::Char(if shift { '|' } else { '\\' }),
+        44 => letter('z', shift),
+        45 => letter('x', shift),
+        46 => letter('c', shift),
+        47 => letter('v', shift),
+        48 => letter('b', shift),
+        49 => letter('n', shift),
+        50 => letter('m', shift),
+        51 => Key::Char(if shift { '<' } else { ',' }),
+        KEY_DOT => Key::Char(if shift { '>' } else { '.' }),
+        53 => Key::Char(if shift { '?' } else { '/' }),
+        KEY_SPACE => Key::Space,
+        102 => Key::Home,
+        104 => Key::PageUp,
+        105 => Key::Left,
+        106 => Key::Right,
+        107 => Key::End,
+        109 => Key::PageDown,
+        111 => Key::Delete,
+        _ => return None,
+    };
+    Some(key)
 }
 
-impl KeyboardProcessor {
-    pub fn handle_event(
-        &mut self,
-        event: wl_keyboard::Event,
-        is_ascii_mode: bool,
-    ) -> KeyboardProcessorResponse {
-        if is_ascii_mode {
-            if let wl_keyboard::Event::Key {
-                key: 42 | 54,
-                state: WEnum::Value(wl_keyboard::KeyState::Pressed),
-                ..
-            } = event
-            {
-                return KeyboardProcessorResponse::Toggle;
-            } else {
-                return KeyboardProcessorResponse::Unhandled(event);
-            }
-        }
-        match event {
-            wl_keyboard::Event::Key { key, state, .. } => match state {
-                WEnum::Value(wl_keyboard::KeyState::Pressed) => match key {
-                    // a-z
-                    16..=25 | 30..=38 | 44..=50 => {
-                        self.handled_keys.insert(key);
-                        KeyboardProcessorResponse::Composing(key)
-                    }
-                    42 | 54 => {
-                        self.handled_keys.insert(key);
-                        KeyboardProcessorResponse::Toggle
-                    }
-                    57 => {
-                        self.handled_keys.insert(key);
-                        KeyboardProcessorResponse::Commit
-                    }
-                    _ => KeyboardProcessorResponse::Unhandled(event),
-                },
-                WEnum::Value(wl_keyboard::KeyState::Released) => {
-                    if self.handled_keys.contains(&key) {
-                        self.handled_keys.remove(&key);
-                        KeyboardProcessorResponse::Ignored
-                    } else {
-                        KeyboardProcessorResponse::Unhandled(event)
-                    }
-                }
-                _ => KeyboardProcessorResponse::Unhandled(event),
-            },
-            _ => KeyboardProcessorResponse::Unhandled(event),
-        }
-    }
-}
-
-pub enum KeyboardProcessorResponse {
-    Composing(u32),
-    DirectlyCommit,
-    Commit,
-    Toggle,
-    Ignored,
-    Unhandled(wl_keyboard::Event),
-    Result(String, Vec<Candidate>),
+fn letter(ch: char, shift: bool) -> Key {
+    Key::Char(if shift { ch.to_ascii_uppercase() } else { ch })
 }
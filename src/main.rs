@@ -1,13 +1,15 @@
 mod composor;
 mod keyboard;
+mod watcher;
 
-use composor::Composor;
-use keyboard::KeyboardProcessorResponse;
-use liushu_core::engine::{candidates::Candidate, Engine};
+use std::sync::Arc;
+
+use composor::{preedit_text, Composor, ComposorResponse};
+use liushu_core::engine::Engine;
 use wayland_client::{
     event_created_child,
     protocol::{wl_keyboard, wl_registry},
-    Connection, Dispatch, QueueHandle,
+    Connection, Dispatch, QueueHandle, WEnum,
 };
 use wayland_protocols::wp::input_method::zv1::client::{
     zwp_input_method_context_v1,
@@ -26,7 +28,10 @@ fn main() {
 
     let xdg_dirs = BaseDirectories::with_prefix("liushu").unwrap();
     let dict_path = xdg_dirs.find_data_file("sunman.trie").unwrap();
-    let engine = Engine::new(dict_path).expect("Open dict error");
+    let engine = Arc::new(Engine::new(dict_path).expect("Open dict error"));
+    if let Err(err) = watcher::watch_dictionaries(engine.clone()) {
+        eprintln!("dictionaries won't be reloaded: {}", err);
+    }
     let composor = Composor::with_engine(engine);
     let mut state = AppState {
         running: true,
@@ -42,14 +47,10 @@ fn main() {
 #[derive(Default)]
 struct AppState {
     running: bool,
-    input: String,
     input_method: Option<zwp_input_method_v1::ZwpInputMethodV1>,
     context: Option<zwp_input_method_context_v1::ZwpInputMethodContextV1>,
     input_serial: u32,
-    candidates: Vec<Candidate>,
     composor: Composor,
-    keyboard_processor: keyboard::KeyboardProcessor,
-    is_ascii_mode: bool,
 }
 
 impl AppState {
@@ -65,45 +66,33 @@ impl AppState {
                 serial,
                 time,
                 key,
-                state,
-            } => {
-                let response = self.composor.process(
-                    self.keyboard_processor
-                        .handle_event(event, self.is_ascii_mode),
-                );
-                match (response, self.context.as_ref()) {
-                    (KeyboardProcessorResponse::Commit, Some(ctx)) => {
-                        if self.input.is_empty() {
-                            ctx.commit_string(self.input_serial, " ".to_string());
-                        } else if !self.candidates.is_empty() {
-                            ctx.commit_string(self.input_serial, self.candidates[0].text.clone());
-                            self.input.clear();
-                            self.composor.clear();
-                        }
-                    }
-                    (KeyboardProcessorResponse::DirectlyCommit, Some(ctx)) => {
-                        ctx.commit_string(self.input_serial, self.input.clone());
-                        self.input.clear();
-                        self.composor.clear();
+                state: key_state,
+            } => match (self.composor.process(event), self.context.as_ref()) {
+                (ComposorResponse::Handled { commit, state }, Some(ctx)) => {
+                    // Modifier releases toggle modes, the application still
+                    // has to see them
+                    if key_state == WEnum::Value(wl_keyboard::KeyState::Released) {
+                        ctx.key(serial, time, key, key_state.into());
                     }
-                    (KeyboardProcessorResponse::Result(input, candidates), Some(ctx)) => {
-                        self.input = input;
-                        self.candidates = candidates;
-                        ctx.preedit_string(
-                            self.input_serial,
-                            self.input.clone(),
-                            self.input.clone(),
-                        );
+                    if let Some(text) = commit {
+                        ctx.commit_string(self.input_serial, text);
                     }
-                    (KeyboardProcessorResponse::Unhandled(_), Some(ctx)) => {
-                        ctx.key(serial, time, key, state.into());
-                    }
-                    (KeyboardProcessorResponse::Toggle, _) => {
-                        self.is_ascii_mode = !self.is_ascii_mode;
-                    }
-                    _ => {}
+                    // The protocol wants the caret as a byte offset, the
+                    // candidates come after the typed code
+                    let cursor = state
+                        .preedit
+                        .char_indices()
+                        .nth(state.cursor)
+                        .map(|(idx, _)| idx)
+                        .unwrap_or(state.preedit.len());
+                    ctx.preedit_cursor(cursor as i32);
+                    ctx.preedit_string(self.input_serial, preedit_text(&state), state.preedit);
                 }
-            }
+                (ComposorResponse::Unhandled, Some(ctx)) => {
+                    ctx.key(serial, time, key, key_state.into());
+                }
+                _ => {}
+            },
             _ => {}
         }
     }
@@ -149,7 +138,7 @@ impl Dispatch<zwp_input_method_v1::ZwpInputMethodV1, ()> for AppState {
                 println!("grab keyboard");
             }
             zwp_input_method_v1::Event::Deactivate { context } => {
-                state.input.clear();
+                state.composor.clear();
                 state.context = None;
                 context.destroy();
                 println!("method inactive");
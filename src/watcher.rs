@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use liushu_core::{
+    dict::{self, BuildRecipe},
+    engine::Engine,
+};
+
+/// Reload the dictionaries of `engine` when they change on disk, in a
+/// background thread.
+///
+/// The directories of the dictionaries are watched rather than the files, as
+/// they are usually replaced instead of written in place. Dictionaries are
+/// rebuilt with the options of their [`BuildRecipe`] when one of its sources
+/// changes, which then triggers the reload. Failures are logged and the old
+/// dictionaries kept.
+pub fn watch_dictionaries(engine: Arc<Engine>) -> io::Result<JoinHandle<()>> {
+    let mut watcher = Watcher {
+        inotify: Inotify::init()?,
+        dirs: HashMap::new(),
+        recipes: vec![],
+    };
+    watcher.update(&engine)?;
+
+    Ok(thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match watcher.inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    eprintln!("watch dictionaries error: {}", err);
+                    return;
+                }
+            };
+
+            let mut reload = false;
+            let mut changed = vec![];
+            for event in events {
+                let (Some(dir), Some(name)) = (watcher.dirs.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+                // Dictionaries are written to a temporary file first
+                if path.extension().is_some_and(|ext| ext == "tmp") {
+                    continue;
+                }
+                if engine.dictionary_paths().contains(&path) {
+                    reload = true;
+                } else {
+                    changed.push(path);
+                }
+            }
+            for path in changed {
+                watcher.rebuild(&path);
+            }
+
+            if reload {
+                match engine.reload() {
+                    Ok(()) => println!("reloaded dictionaries"),
+                    Err(err) => {
+                        eprintln!(
+                            "reload dictionaries error, keeping the old ones: {}",
+                            err.full_message()
+                        )
+                    }
+                }
+                // The dictionaries may have been rebuilt from other sources
+                if let Err(err) = watcher.update(&engine) {
+                    eprintln!("watch dictionaries error: {}", err);
+                }
+            }
+        }
+    }))
+}
+
+struct Watcher {
+    inotify: Inotify,
+    /// Watched directories of the dictionaries and their sources
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    /// Recipes of the dictionaries of the engine that can be rebuilt
+    recipes: Vec<(PathBuf, BuildRecipe)>,
+}
+
+impl Watcher {
+    /// Read the recipes of the dictionaries again and watch the directories
+    /// of their sources.
+    fn update(&mut self, engine: &Engine) -> io::Result<()> {
+        self.recipes = engine
+            .dictionary_paths()
+            .iter()
+            .filter_map(|path| {
+                let recipe = dict::read_header(path).ok()?.recipe?;
+                Some((path.clone(), recipe))
+            })
+            .collect();
+
+        let sources = self.recipes.iter().flat_map(|(_, recipe)| {
+            recipe
+                .inputs
+                .iter()
+                .chain(recipe.options.heteronyms.as_ref())
+        });
+        let paths: Vec<&Path> = engine
+            .dictionary_paths()
+            .iter()
+            .chain(sources)
+            .filter_map(|path| path.parent())
+            .collect();
+        for dir in paths {
+            if !self.dirs.values().any(|watched| watched == dir) {
+                let wd = self
+                    .inotify
+                    .watches()
+                    .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+                self.dirs.insert(wd, dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the dictionaries reading a changed file again.
+    fn rebuild(&self, source: &Path) {
+        for (dict_path, recipe) in &self.recipes {
+            if !recipe.uses(source) {
+                continue;
+            }
+            if let Err(err) = dict::rebuild_if_changed(dict_path) {
+                eprintln!(
+                    "build {} error: {}",
+                    dict_path.display(),
+                    err.full_message()
+                );
+            }
+        }
+    }
+}